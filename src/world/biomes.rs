@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use super::DataGrid;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BiomeTable {
+    /// Biome of cells that no rule matches.
+    pub default: u8,
+    pub biomes: Vec<BiomeRule>,
+}
+
+/// A biome applies to a cell when every condition it sets holds. Rules are tried in table order
+/// and the first match wins, so catch-all biomes belong at the end.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BiomeRule {
+    pub id: u8,
+    pub name: String,
+    pub color: [u8; 3],
+    pub below_sea_level: Option<bool>,
+    pub lake: Option<bool>,
+    pub min_height_above_sea: Option<f64>,
+    pub max_height_above_sea: Option<f64>,
+    /// Distance in cells to the coastline, positive on land and negative at sea.
+    pub max_coast_distance: Option<f64>,
+    pub min_temperature: Option<f64>,
+    pub max_temperature: Option<f64>,
+    pub min_moisture: Option<f64>,
+    pub max_moisture: Option<f64>,
+}
+
+pub struct BiomeInput {
+    pub height_above_sea: f64,
+    pub coast_distance: f64,
+    pub lake: bool,
+    pub temperature: f64,
+    pub moisture: f64,
+}
+
+fn within(value: f64, min: Option<f64>, max: Option<f64>) -> bool {
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+impl BiomeRule {
+    pub fn matches(&self, input: &BiomeInput) -> bool {
+        self.below_sea_level
+            .is_none_or(|below| below == (input.height_above_sea < 0.0))
+            && self.lake.is_none_or(|lake| lake == input.lake)
+            && within(
+                input.height_above_sea,
+                self.min_height_above_sea,
+                self.max_height_above_sea,
+            )
+            && within(input.coast_distance, None, self.max_coast_distance)
+            && within(
+                input.temperature,
+                self.min_temperature,
+                self.max_temperature,
+            )
+            && within(input.moisture, self.min_moisture, self.max_moisture)
+    }
+}
+
+impl BiomeTable {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let source = fs::read_to_string(path).expect("Could not read biome table file");
+        let table: Self = toml::from_str(&source).expect("Could not parse biome table");
+        if !table.biomes.iter().any(|biome| biome.id == table.default) {
+            panic!(
+                "Biome table default {} is not one of its biomes",
+                table.default
+            );
+        }
+        table
+    }
+    pub fn classify_cell(&self, input: &BiomeInput) -> u8 {
+        self.biomes
+            .iter()
+            .find(|biome| biome.matches(input))
+            .map_or(self.default, |biome| biome.id)
+    }
+    pub fn classify(
+        &self,
+        sea_level: f64,
+        heights: &DataGrid,
+        coast_distance: &DataGrid,
+        lake_labels: &DataGrid<u32>,
+        temperature: &DataGrid,
+        moisture: &DataGrid,
+    ) -> DataGrid<u8> {
+        println!("Classifying biomes");
+        heights.map_coords(|x, y, height| {
+            let index = heights.index_of(x, y);
+            self.classify_cell(&BiomeInput {
+                height_above_sea: height - sea_level,
+                coast_distance: coast_distance.values[index],
+                lake: lake_labels.values[index] != 0,
+                temperature: temperature.values[index],
+                moisture: moisture.values[index],
+            })
+        })
+    }
+    /// Colours indexed by biome id. Ids without a biome are black.
+    pub fn palette(&self) -> Vec<[u8; 3]> {
+        let size = self
+            .biomes
+            .iter()
+            .map(|biome| biome.id as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut palette = vec![[0, 0, 0]; size];
+        for biome in self.biomes.iter().rev() {
+            palette[biome.id as usize] = biome.color;
+        }
+        palette
+    }
+}
+
+pub fn render_biomes(biomes: &DataGrid<u8>, palette: &[[u8; 3]]) -> RgbImage {
+    RgbImage::from_fn(biomes.width as u32, biomes.height as u32, |x, y| {
+        let id = biomes.get(x as usize, y as usize).unwrap() as usize;
+        Rgb(palette.get(id).copied().unwrap_or([0, 0, 0]))
+    })
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Ways of combining a top layer with a bottom layer, named as in image editors. The
+/// contrast modes assume values in `[0, 1]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    /// Replaces the bottom with the top, so opacity and mask fade linearly between the two.
+    #[default]
+    Lerp,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    HardLight,
+    Add,
+    Subtract,
+    Min,
+    Max,
+    Difference,
+}
+
+impl BlendMode {
+    pub fn apply(&self, bottom: f64, top: f64) -> f64 {
+        match self {
+            BlendMode::Lerp => top,
+            BlendMode::Multiply => bottom * top,
+            BlendMode::Screen => 1.0 - (1.0 - bottom) * (1.0 - top),
+            BlendMode::Overlay => BlendMode::HardLight.apply(top, bottom),
+            BlendMode::SoftLight => (1.0 - 2.0 * top) * bottom * bottom + 2.0 * top * bottom,
+            BlendMode::HardLight => {
+                if top <= 0.5 {
+                    2.0 * bottom * top
+                } else {
+                    1.0 - 2.0 * (1.0 - bottom) * (1.0 - top)
+                }
+            }
+            BlendMode::Add => bottom + top,
+            BlendMode::Subtract => bottom - top,
+            BlendMode::Min => f64::min(bottom, top),
+            BlendMode::Max => f64::max(bottom, top),
+            BlendMode::Difference => (bottom - top).abs(),
+        }
+    }
+}
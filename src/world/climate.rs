@@ -0,0 +1,188 @@
+use noise::{Perlin, Seedable};
+use serde::{Deserialize, Serialize};
+
+use super::{get_noise, DataGrid, River, WorldConfig};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClimateSettings {
+    /// Row of the map, as a fraction of its height, that lies on the equator.
+    pub equator: f64,
+    pub equator_temperature: f64,
+    pub pole_temperature: f64,
+    /// Degrees lost between sea level and a height of 1.0.
+    pub lapse_rate: f64,
+    pub temperature_noise: f64,
+    pub wind_direction: [f64; 2],
+    /// Distance in cells over which the influence of open water halves.
+    pub water_distance: f64,
+    pub water_influence: f64,
+    pub base_rainfall: f64,
+    pub orographic_rainfall: f64,
+}
+
+impl Default for ClimateSettings {
+    fn default() -> Self {
+        Self {
+            equator: 0.5,
+            equator_temperature: 30.0,
+            pole_temperature: -25.0,
+            lapse_rate: 40.0,
+            temperature_noise: 3.0,
+            wind_direction: [1.0, 0.0],
+            water_distance: 32.0,
+            water_influence: 0.4,
+            base_rainfall: 0.01,
+            orographic_rainfall: 40.0,
+        }
+    }
+}
+
+pub fn temperature(
+    config: &WorldConfig,
+    sea_level: f64,
+    heights: &DataGrid,
+    settings: &ClimateSettings,
+) -> DataGrid {
+    println!("Generating temperature");
+    let noise = get_noise(
+        config,
+        Perlin::new().set_seed(config.noise_seed("temperature")),
+        1.0,
+        0.0,
+        1.0,
+    );
+    let rows = f64::max(heights.height as f64 - 1.0, 1.0);
+    heights.map_coords(|x, y, height| {
+        let latitude = f64::min((y as f64 / rows - settings.equator).abs() * 2.0, 1.0);
+        let temperature = settings.pole_temperature
+            + (settings.equator_temperature - settings.pole_temperature)
+                * f64::cos(latitude * std::f64::consts::FRAC_PI_2);
+        let elevation = f64::max(height - sea_level, 0.0);
+        temperature - elevation * settings.lapse_rate
+            + noise.values[noise.index_of(x, y)] * settings.temperature_noise
+    })
+}
+
+pub fn water_mask(
+    sea_level: f64,
+    heights: &DataGrid,
+    lake_labels: &DataGrid<u32>,
+    rivers: &[River],
+) -> DataGrid<bool> {
+    let mut water = heights.zip_with(lake_labels, |height, lake| height < sea_level || lake != 0);
+    for river in rivers {
+        for point in river.land_points() {
+            water.set(point[0] as usize, point[1] as usize, true);
+        }
+    }
+    water
+}
+
+/// Two-pass chamfer approximation of the distance in cells to the nearest `true` cell.
+pub fn distance_to(mask: &DataGrid<bool>) -> DataGrid {
+    let diagonal = std::f64::consts::SQRT_2;
+    let mut distances = mask.map(|value| if value { 0.0 } else { f64::INFINITY });
+    let forward = [
+        (-1, 0, 1.0),
+        (-1, -1, diagonal),
+        (0, -1, 1.0),
+        (1, -1, diagonal),
+    ];
+    let backward = [
+        (1, 0, 1.0),
+        (1, 1, diagonal),
+        (0, 1, 1.0),
+        (-1, 1, diagonal),
+    ];
+    let relax = |distances: &mut DataGrid, x: usize, y: usize, offsets: &[(isize, isize, f64)]| {
+        let index = distances.index_of(x, y);
+        for (offset_x, offset_y, cost) in offsets {
+            let neighbour_x = x as isize + offset_x;
+            let neighbour_y = y as isize + offset_y;
+            if neighbour_x < 0 || neighbour_y < 0 {
+                continue;
+            }
+            if let Some(distance) = distances.get(neighbour_x as usize, neighbour_y as usize) {
+                distances.values[index] = f64::min(distances.values[index], distance + cost);
+            }
+        }
+    };
+    for y in 0..mask.height {
+        for x in 0..mask.width {
+            relax(&mut distances, x, y, &forward);
+        }
+    }
+    for y in (0..mask.height).rev() {
+        for x in (0..mask.width).rev() {
+            relax(&mut distances, x, y, &backward);
+        }
+    }
+    distances
+}
+
+/// Carries humidity across the map with the prevailing wind. Air is saturated over water and
+/// loses moisture as rain, most of all where it is pushed up slopes, which leaves a rain shadow
+/// on the lee side of high terrain.
+pub fn moisture(
+    sea_level: f64,
+    heights: &DataGrid,
+    water: &DataGrid<bool>,
+    settings: &ClimateSettings,
+) -> DataGrid {
+    println!("Generating moisture");
+    let [wind_x, wind_y] = settings.wind_direction;
+    let wind_length = f64::max(f64::sqrt(wind_x * wind_x + wind_y * wind_y), f64::EPSILON);
+    let (wind_x, wind_y) = (wind_x / wind_length, wind_y / wind_length);
+
+    let mut order: Vec<usize> = (0..heights.len()).collect();
+    let projection = |index: usize| {
+        let (x, y) = heights.coords_of(index);
+        x as f64 * wind_x + y as f64 * wind_y
+    };
+    order.sort_by(|a, b| projection(*a).total_cmp(&projection(*b)));
+
+    let ground = heights.map(|height| f64::max(height, sea_level));
+    let mut humidity = DataGrid::new(heights.width, heights.height);
+    let mut rainfall = DataGrid::new(heights.width, heights.height);
+    for index in order {
+        let (x, y) = heights.coords_of(index);
+        if water.values[index] {
+            humidity.values[index] = 1.0;
+            rainfall.values[index] = settings.base_rainfall;
+            continue;
+        }
+        let upwind_x = (x as f64 - wind_x).round();
+        let upwind_y = (y as f64 - wind_y).round();
+        let upwind = if upwind_x < 0.0 || upwind_y < 0.0 {
+            None
+        } else {
+            ground
+                .get(upwind_x as usize, upwind_y as usize)
+                .map(|height| (upwind_x as usize, upwind_y as usize, height))
+        };
+        let (incoming, rise) = match upwind {
+            Some((upwind_x, upwind_y, upwind_height)) => (
+                humidity.values[humidity.index_of(upwind_x, upwind_y)],
+                f64::max(ground.values[index] - upwind_height, 0.0),
+            ),
+            None => (1.0, 0.0),
+        };
+        let rain = incoming
+            * f64::min(
+                settings.base_rainfall + rise * settings.orographic_rainfall,
+                1.0,
+            );
+        humidity.values[index] = incoming - rain;
+        rainfall.values[index] = rain;
+    }
+
+    let distances = distance_to(water);
+    let max_rainfall = f64::max(rainfall.max_value(), f64::EPSILON);
+    distances.zip_with(&rainfall, |distance, rain| {
+        let proximity = f64::powf(0.5, distance / settings.water_distance);
+        let moisture = settings.water_influence * proximity
+            + (1.0 - settings.water_influence) * rain / max_rainfall;
+        moisture.clamp(0.0, 1.0)
+    })
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use super::{climate::distance_to, DataGrid};
+
+/// Finds the sea level that leaves `land_fraction` of the cells above water.
+pub fn solve_sea_level(heights: &DataGrid, land_fraction: f64) -> f64 {
+    let mut sorted = heights.values.clone();
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    sorted.sort_by(f64::total_cmp);
+    let water_fraction = (1.0 - land_fraction).clamp(0.0, 1.0);
+    let index = ((sorted.len() as f64 * water_fraction) as usize).min(sorted.len() - 1);
+    sorted[index]
+}
+
+pub fn land_mask(heights: &DataGrid, sea_level: f64) -> DataGrid<bool> {
+    heights.map(|height| height >= sea_level)
+}
+
+/// Distance in cells to the coastline, positive on land and negative at sea.
+pub fn distance_to_coast(heights: &DataGrid, sea_level: f64) -> DataGrid {
+    let land = land_mask(heights, sea_level);
+    let water = land.map(|land| !land);
+    let distance_to_water = distance_to(&water);
+    let distance_to_land = distance_to(&land);
+    land.map_coords(|x, y, land| {
+        let index = heights.index_of(x, y);
+        if land {
+            distance_to_water.values[index]
+        } else {
+            -distance_to_land.values[index]
+        }
+    })
+}
+
+fn horizontal_edge(heights: &DataGrid, x: usize, y: usize) -> usize {
+    heights.index_of(x, y) * 2
+}
+
+fn vertical_edge(heights: &DataGrid, x: usize, y: usize) -> usize {
+    heights.index_of(x, y) * 2 + 1
+}
+
+fn edge_point(heights: &DataGrid, sea_level: f64, edge: usize) -> [f64; 2] {
+    let (x, y) = heights.coords_of(edge / 2);
+    let (end_x, end_y) = if edge.is_multiple_of(2) {
+        (x + 1, y)
+    } else {
+        (x, y + 1)
+    };
+    let start = heights.values[heights.index_of(x, y)];
+    let end = heights.values[heights.index_of(end_x, end_y)];
+    let t = if (end - start).abs() <= f64::EPSILON {
+        0.5
+    } else {
+        ((sea_level - start) / (end - start)).clamp(0.0, 1.0)
+    };
+    [
+        x as f64 + (end_x - x) as f64 * t,
+        y as f64 + (end_y - y) as f64 * t,
+    ]
+}
+
+/// Traces the sea level contour with marching squares. Contours that leave the map are open
+/// polylines, every other contour is closed with its first point repeated at the end.
+pub fn coastlines(heights: &DataGrid, sea_level: f64) -> Vec<Vec<[f64; 2]>> {
+    println!("Extracting coastlines");
+    let mut segments = Vec::new();
+    for y in 0..heights.height.saturating_sub(1) {
+        for x in 0..heights.width.saturating_sub(1) {
+            let corner = |x: usize, y: usize| heights.values[heights.index_of(x, y)];
+            let corners = [
+                corner(x, y),
+                corner(x + 1, y),
+                corner(x + 1, y + 1),
+                corner(x, y + 1),
+            ];
+            let case = corners
+                .iter()
+                .fold(0, |case, height| case << 1 | (*height >= sea_level) as u8);
+            let top = horizontal_edge(heights, x, y);
+            let right = vertical_edge(heights, x + 1, y);
+            let bottom = horizontal_edge(heights, x, y + 1);
+            let left = vertical_edge(heights, x, y);
+            let center_is_land = corners.iter().sum::<f64>() / 4.0 >= sea_level;
+            match case {
+                0b0001 | 0b1110 => segments.push((left, bottom)),
+                0b0010 | 0b1101 => segments.push((bottom, right)),
+                0b0011 | 0b1100 => segments.push((left, right)),
+                0b0100 | 0b1011 => segments.push((top, right)),
+                0b0110 | 0b1001 => segments.push((top, bottom)),
+                0b0111 | 0b1000 => segments.push((left, top)),
+                0b0101 if center_is_land => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+                0b1010 if !center_is_land => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+                0b0101 | 0b1010 => {
+                    segments.push((left, bottom));
+                    segments.push((top, right));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut edge_segments: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        edge_segments.entry(*a).or_default().push(i);
+        edge_segments.entry(*b).or_default().push(i);
+    }
+    let mut starts: Vec<usize> = edge_segments
+        .iter()
+        .filter(|(_, attached)| attached.len() == 1)
+        .map(|(edge, _)| *edge)
+        .collect();
+    starts.sort_unstable();
+    starts.extend(segments.iter().map(|(a, _)| *a));
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = Vec::new();
+    for start in starts {
+        let mut edge = start;
+        let mut line = Vec::new();
+        while let Some(segment) = edge_segments[&edge]
+            .iter()
+            .copied()
+            .find(|segment| !used[*segment])
+        {
+            used[segment] = true;
+            if line.is_empty() {
+                line.push(edge_point(heights, sea_level, edge));
+            }
+            let (a, b) = segments[segment];
+            edge = if a == edge { b } else { a };
+            line.push(edge_point(heights, sea_level, edge));
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use super::DataGrid;
+
+const BAYER_4: [[f64; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorStop {
+    pub value: f64,
+    pub color: [u8; 3],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RampMode {
+    /// Blends linearly between neighbouring stops.
+    #[default]
+    Smooth,
+    /// Gives every value the colour of the highest stop at or below it, like the layers of a
+    /// printed atlas.
+    Bands,
+}
+
+/// Maps values to colours through stops in ascending order of value, which ramps loaded from a
+/// file are sorted into. Values outside the stops take the colour of the nearest end.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorRamp {
+    pub stops: Vec<ColorStop>,
+    #[serde(default)]
+    pub mode: RampMode,
+    /// Strength of ordered dithering, in the units of the values. It mixes the colours either
+    /// side of a band edge, or of an 8-bit step in a smooth ramp, in a fine regular pattern.
+    #[serde(default)]
+    pub dither: Option<f64>,
+}
+
+impl ColorRamp {
+    pub fn new(stops: Vec<ColorStop>, mode: RampMode) -> Self {
+        Self {
+            stops,
+            mode,
+            dither: None,
+        }
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).expect("Could not read colour ramp file");
+        let mut ramp: Self = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => ron::from_str(&source).expect("Could not parse colour ramp RON"),
+            _ => toml::from_str(&source).expect("Could not parse colour ramp TOML"),
+        };
+        ramp.stops.sort_by(|a, b| a.value.total_cmp(&b.value));
+        ramp
+    }
+
+    pub fn color(&self, value: f64) -> [f64; 3] {
+        let to_f64 = |color: [u8; 3]| color.map(|channel| channel as f64);
+        let upper = match self.stops.iter().position(|stop| stop.value > value) {
+            Some(upper) => upper,
+            None => {
+                return self
+                    .stops
+                    .last()
+                    .map_or([0.0; 3], |stop| to_f64(stop.color))
+            }
+        };
+        if upper == 0 {
+            return to_f64(self.stops[0].color);
+        }
+        let (a, b) = (&self.stops[upper - 1], &self.stops[upper]);
+        match self.mode {
+            RampMode::Bands => to_f64(a.color),
+            RampMode::Smooth => {
+                let t = (value - a.value) / (b.value - a.value);
+                let (a, b) = (to_f64(a.color), to_f64(b.color));
+                [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+            }
+        }
+    }
+
+    /// Colour of a value at a pixel, dithered if the ramp asks for it.
+    pub fn color_at(&self, value: f64, x: usize, y: usize) -> [f64; 3] {
+        match self.dither {
+            Some(dither) => {
+                let threshold = (BAYER_4[y % 4][x % 4] + 0.5) / 16.0 - 0.5;
+                self.color(value + threshold * dither)
+            }
+            None => self.color(value),
+        }
+    }
+}
+
+pub fn render(data_grid: &DataGrid, ramp: &ColorRamp) -> RgbImage {
+    RgbImage::from_fn(data_grid.width as u32, data_grid.height as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let color = ramp.color_at(data_grid.values[data_grid.index_of(x, y)], x, y);
+        Rgb(color.map(|channel| channel.round().clamp(0.0, 255.0) as u8))
+    })
+}
@@ -0,0 +1,83 @@
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ClimateSettings, DepressionSettings, HydraulicErosionSettings, KingdomSettings, NameSettings,
+    RegionSettings, RiverSettings, RoadSettings, SettlementSettings, StampSettings,
+    ThermalErosionSettings,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+    pub seed: u64,
+    pub width: usize,
+    pub height: usize,
+    pub tiling: f64,
+    pub sea_level: f64,
+    /// When set, the sea level is solved so this fraction of the map is land.
+    pub land_fraction: Option<f64>,
+    pub stamps: Vec<StampSettings>,
+    pub thermal_erosion: Option<ThermalErosionSettings>,
+    pub hydraulic_erosion: Option<HydraulicErosionSettings>,
+    pub depressions: Option<DepressionSettings>,
+    pub rivers: Option<RiverSettings>,
+    pub climate: Option<ClimateSettings>,
+    pub biome_table: Option<String>,
+    pub regions: Option<RegionSettings>,
+    pub kingdoms: Option<KingdomSettings>,
+    pub settlements: Option<SettlementSettings>,
+    pub roads: Option<RoadSettings>,
+    pub names: Option<NameSettings>,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            width: 1024,
+            height: 1024,
+            tiling: 8.0,
+            sea_level: 0.4,
+            land_fraction: None,
+            stamps: vec![StampSettings::default()],
+            thermal_erosion: None,
+            hydraulic_erosion: None,
+            depressions: None,
+            rivers: None,
+            climate: Some(ClimateSettings::default()),
+            biome_table: Some("resources/biomes.toml".to_string()),
+            regions: Some(RegionSettings::default()),
+            kingdoms: Some(KingdomSettings::default()),
+            settlements: Some(SettlementSettings::default()),
+            roads: Some(RoadSettings::default()),
+            names: Some(NameSettings::default()),
+        }
+    }
+}
+
+impl WorldConfig {
+    /// Derives an independent seed for a named subsystem, so adding random draws to one stage
+    /// does not shift the output of any other.
+    pub fn stream_seed(&self, stream: &str) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in stream.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        split_mix(self.seed ^ hash)
+    }
+    pub fn noise_seed(&self, stream: &str) -> u32 {
+        (self.stream_seed(stream) >> 32) as u32
+    }
+    pub fn rng(&self, stream: &str) -> StdRng {
+        StdRng::seed_from_u64(self.stream_seed(stream))
+    }
+}
+
+fn split_mix(value: u64) -> u64 {
+    let mut value = value.wrapping_add(0x9e3779b97f4a7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
@@ -0,0 +1,357 @@
+use std::ops::{Add, Mul, Sub};
+use std::thread;
+
+use image::{GenericImageView, GrayImage};
+use serde::{Deserialize, Serialize};
+
+use super::{remap, BlendMode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Neighbourhood {
+    Four,
+    Eight,
+}
+
+impl Neighbourhood {
+    pub fn offsets(&self) -> &'static [(isize, isize)] {
+        match self {
+            Neighbourhood::Four => &[(0, -1), (1, 0), (0, 1), (-1, 0)],
+            Neighbourhood::Eight => &[
+                (0, -1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+                (0, 1),
+                (-1, 1),
+                (-1, 0),
+                (-1, -1),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataGrid<T = f64> {
+    pub values: Vec<T>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<T: Copy> DataGrid<T> {
+    pub fn new(width: usize, height: usize) -> Self
+    where
+        T: Default,
+    {
+        Self::filled(width, height, T::default())
+    }
+    pub fn filled(width: usize, height: usize, value: T) -> Self {
+        Self {
+            values: vec![value; width * height],
+            width,
+            height,
+        }
+    }
+    pub fn from_vec(width: usize, height: usize, values: Vec<T>) -> Self {
+        assert_eq!(
+            values.len(),
+            width * height,
+            "Data grid values do not match dimensions"
+        );
+        Self {
+            values,
+            width,
+            height,
+        }
+    }
+    pub fn from_fn<F: FnMut(usize, usize) -> T>(width: usize, height: usize, mut func: F) -> Self {
+        let mut values = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                values.push(func(x, y));
+            }
+        }
+        Self {
+            values,
+            width,
+            height,
+        }
+    }
+    pub fn from_fn_parallel<F: Fn(usize, usize) -> T + Sync>(
+        width: usize,
+        height: usize,
+        threads: usize,
+        func: F,
+    ) -> Self
+    where
+        T: Default + Send,
+    {
+        let mut data_grid = Self::new(width, height);
+        if data_grid.is_empty() {
+            return data_grid;
+        }
+        let rows_per_band = height.div_ceil(threads.max(1));
+        thread::scope(|scope| {
+            for (band, band_values) in data_grid
+                .values
+                .chunks_mut(rows_per_band * width)
+                .enumerate()
+            {
+                let func = &func;
+                scope.spawn(move || {
+                    let y_start = band * rows_per_band;
+                    for (i, value) in band_values.iter_mut().enumerate() {
+                        *value = func(i % width, y_start + i / width);
+                    }
+                });
+            }
+        });
+        data_grid
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+    pub fn index_of(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+    pub fn coords_of(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+    pub fn get(&self, x: usize, y: usize) -> Option<T> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        Some(self.values[self.index_of(x, y)])
+    }
+    pub fn set(&mut self, x: usize, y: usize, value: T) {
+        if !self.contains(x, y) {
+            return;
+        }
+        let index = self.index_of(x, y);
+        self.values[index] = value;
+    }
+    pub fn neighbours(
+        &self,
+        x: usize,
+        y: usize,
+        neighbourhood: Neighbourhood,
+    ) -> impl Iterator<Item = (usize, usize)> {
+        let (width, height) = (self.width as isize, self.height as isize);
+        neighbourhood
+            .offsets()
+            .iter()
+            .map(move |(offset_x, offset_y)| (x as isize + offset_x, y as isize + offset_y))
+            .filter(move |(x, y)| *x >= 0 && *y >= 0 && *x < width && *y < height)
+            .map(|(x, y)| (x as usize, y as usize))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.values.iter()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.values.iter_mut()
+    }
+    pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        let width = self.width;
+        (0..self.height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+    }
+    pub fn iter_coords(&self) -> impl Iterator<Item = (usize, usize, T)> + '_ {
+        self.coords()
+            .zip(self.values.iter())
+            .map(|((x, y), value)| (x, y, *value))
+    }
+    pub fn map<U: Copy, F: FnMut(T) -> U>(&self, mut func: F) -> DataGrid<U> {
+        DataGrid {
+            values: self.values.iter().map(|value| func(*value)).collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+    pub fn map_coords<U: Copy, F: FnMut(usize, usize, T) -> U>(&self, mut func: F) -> DataGrid<U> {
+        DataGrid {
+            values: self
+                .iter_coords()
+                .map(|(x, y, value)| func(x, y, value))
+                .collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+    pub fn map_mut<F: FnMut(T) -> T>(&mut self, mut func: F) {
+        self.values
+            .iter_mut()
+            .for_each(|value| *value = func(*value));
+    }
+    pub fn zip_with<U: Copy, V: Copy, F: FnMut(T, U) -> V>(
+        &self,
+        other: &DataGrid<U>,
+        mut func: F,
+    ) -> DataGrid<V> {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "Data grid dimensions do not match"
+        );
+        DataGrid {
+            values: self
+                .values
+                .iter()
+                .zip(other.values.iter())
+                .map(|(a, b)| func(*a, *b))
+                .collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+    pub fn fold<A, F: FnMut(A, T) -> A>(&self, initial_value: A, func: F) -> A {
+        self.values.iter().copied().fold(initial_value, func)
+    }
+}
+
+impl DataGrid<f64> {
+    pub fn from_image(image: &GrayImage) -> Self {
+        Self::from_fn(image.width() as usize, image.height() as usize, |x, y| {
+            let pixel_value = unsafe { image.unsafe_get_pixel(x as u32, y as u32)[0] };
+            pixel_value as f64 / 256.0
+        })
+    }
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            image::Luma([(self.values[self.index_of(x as usize, y as usize)] * 256.0) as u8])
+        })
+    }
+    pub fn min_value(&self) -> f64 {
+        self.fold(f64::INFINITY, f64::min)
+    }
+    pub fn max_value(&self) -> f64 {
+        self.fold(f64::NEG_INFINITY, f64::max)
+    }
+    /// Stretches the values to span `[min, max]`. A constant grid maps to `min`.
+    pub fn remap(&self, min: f64, max: f64) -> Self {
+        let mut data_grid = self.clone();
+        data_grid.remap_mut(min, max);
+        data_grid
+    }
+    pub fn remap_mut(&mut self, min: f64, max: f64) {
+        let min_value = self.min_value();
+        let max_value = self.max_value();
+        println!("Remapping: {} - {}", min_value, max_value);
+        if min_value == max_value {
+            self.values.fill(min);
+            return;
+        }
+        self.map_mut(|value| remap(value, min_value, max_value, min, max));
+    }
+    /// Bilinear sample at fractional coordinates, clamped to the edges of the grid.
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        assert!(!self.is_empty(), "Could not sample an empty grid");
+        let x = x.clamp(0.0, (self.width - 1) as f64);
+        let y = y.clamp(0.0, (self.height - 1) as f64);
+        let (x_0, y_0) = (x.floor() as usize, y.floor() as usize);
+        let (x_1, y_1) = (
+            usize::min(x_0 + 1, self.width - 1),
+            usize::min(y_0 + 1, self.height - 1),
+        );
+        let (fx, fy) = (x - x_0 as f64, y - y_0 as f64);
+        let value = |x: usize, y: usize| self.values[self.index_of(x, y)];
+        let top = value(x_0, y_0) * (1.0 - fx) + value(x_1, y_0) * fx;
+        let bottom = value(x_0, y_1) * (1.0 - fx) + value(x_1, y_1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+    /// Bilinear resize, keeping the corner cells on the corners. An empty grid can only be
+    /// resized to another empty one.
+    pub fn resample(&self, width: usize, height: usize) -> Self {
+        let scale = |size: usize, new_size: usize| {
+            if new_size > 1 {
+                (size - 1) as f64 / (new_size - 1) as f64
+            } else {
+                0.0
+            }
+        };
+        let (scale_x, scale_y) = (scale(self.width, width), scale(self.height, height));
+        Self::from_fn(width, height, |x, y| {
+            self.sample(x as f64 * scale_x, y as f64 * scale_y)
+        })
+    }
+    /// Blends `other` onto this grid at a whole-cell offset, at full opacity and without a mask.
+    pub fn blend_mut(
+        &mut self,
+        other: &DataGrid,
+        offset_x: isize,
+        offset_y: isize,
+        mode: BlendMode,
+    ) {
+        self.blend(other, [offset_x as f64, offset_y as f64], mode, 1.0, None);
+    }
+    /// Blends `top`, shifted by a possibly fractional offset, onto this grid. The blended value
+    /// is faded in by `opacity` and by `mask`, which covers this grid and is clamped to
+    /// `[0, 1]`.
+    pub fn blend(
+        &mut self,
+        top: &DataGrid,
+        offset: [f64; 2],
+        mode: BlendMode,
+        opacity: f64,
+        mask: Option<&DataGrid>,
+    ) {
+        if top.values.is_empty() {
+            return;
+        }
+        let start_x = offset[0].floor().max(0.0) as usize;
+        let start_y = offset[1].floor().max(0.0) as usize;
+        let end_x = (offset[0] + top.width as f64).ceil().max(0.0) as usize;
+        let end_y = (offset[1] + top.height as f64).ceil().max(0.0) as usize;
+        for y in start_y..usize::min(end_y, self.height) {
+            for x in start_x..usize::min(end_x, self.width) {
+                let top_x = x as f64 - offset[0];
+                let top_y = y as f64 - offset[1];
+                if top_x < 0.0
+                    || top_y < 0.0
+                    || top_x > (top.width - 1) as f64
+                    || top_y > (top.height - 1) as f64
+                {
+                    continue;
+                }
+                let index = self.index_of(x, y);
+                let factor = match mask {
+                    Some(mask) => mask.get(x, y).unwrap_or(0.0).clamp(0.0, 1.0) * opacity,
+                    None => opacity,
+                };
+                let bottom = self.values[index];
+                let blended = mode.apply(bottom, top.sample(top_x, top_y));
+                self.values[index] = bottom + (blended - bottom) * factor;
+            }
+        }
+    }
+}
+
+macro_rules! impl_data_grid_op {
+    ($trait:ident, $method:ident) => {
+        impl<'a, T: Copy + $trait<Output = T>> $trait for &'a DataGrid<T> {
+            type Output = DataGrid<T>;
+            fn $method(self, other: &'a DataGrid<T>) -> DataGrid<T> {
+                self.zip_with(other, |a, b| a.$method(b))
+            }
+        }
+        impl<T: Copy + $trait<Output = T>> $trait for DataGrid<T> {
+            type Output = DataGrid<T>;
+            fn $method(self, other: DataGrid<T>) -> DataGrid<T> {
+                (&self).$method(&other)
+            }
+        }
+        impl<'a, T: Copy + $trait<Output = T>> $trait<&'a DataGrid<T>> for DataGrid<T> {
+            type Output = DataGrid<T>;
+            fn $method(self, other: &'a DataGrid<T>) -> DataGrid<T> {
+                (&self).$method(other)
+            }
+        }
+    };
+}
+
+impl_data_grid_op!(Add, add);
+impl_data_grid_op!(Sub, sub);
+impl_data_grid_op!(Mul, mul);
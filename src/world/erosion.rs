@@ -0,0 +1,224 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{data_grid::Neighbourhood, DataGrid};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HydraulicErosionSettings {
+    pub droplets: usize,
+    pub max_lifetime: usize,
+    pub inertia: f64,
+    pub capacity: f64,
+    pub min_slope: f64,
+    pub deposition: f64,
+    pub erosion: f64,
+    pub evaporation: f64,
+    pub gravity: f64,
+}
+
+impl Default for HydraulicErosionSettings {
+    fn default() -> Self {
+        Self {
+            droplets: 70000,
+            max_lifetime: 64,
+            inertia: 0.05,
+            capacity: 4.0,
+            min_slope: 0.001,
+            deposition: 0.3,
+            erosion: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalErosionSettings {
+    pub iterations: usize,
+    /// Largest height difference between adjacent cells that stays in place.
+    pub talus: f64,
+    pub strength: f64,
+    pub neighbourhood: Neighbourhood,
+}
+
+impl Default for ThermalErosionSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 50,
+            talus: 0.004,
+            strength: 0.5,
+            neighbourhood: Neighbourhood::Eight,
+        }
+    }
+}
+
+pub struct HydraulicErosion {
+    pub heights: DataGrid,
+    pub sediment: DataGrid,
+    pub water_flow: DataGrid,
+}
+
+fn height_and_gradient(heights: &DataGrid, x: f64, y: f64) -> (f64, f64, f64) {
+    let cell_x = x as usize;
+    let cell_y = y as usize;
+    let u = x - cell_x as f64;
+    let v = y - cell_y as f64;
+    let top_left = heights.values[heights.index_of(cell_x, cell_y)];
+    let top_right = heights.values[heights.index_of(cell_x + 1, cell_y)];
+    let bottom_left = heights.values[heights.index_of(cell_x, cell_y + 1)];
+    let bottom_right = heights.values[heights.index_of(cell_x + 1, cell_y + 1)];
+    let gradient_x = (top_right - top_left) * (1.0 - v) + (bottom_right - bottom_left) * v;
+    let gradient_y = (bottom_left - top_left) * (1.0 - u) + (bottom_right - top_right) * u;
+    let height = top_left * (1.0 - u) * (1.0 - v)
+        + top_right * u * (1.0 - v)
+        + bottom_left * (1.0 - u) * v
+        + bottom_right * u * v;
+    (height, gradient_x, gradient_y)
+}
+
+fn distribute(data_grid: &mut DataGrid, x: f64, y: f64, amount: f64) {
+    let cell_x = x as usize;
+    let cell_y = y as usize;
+    let u = x - cell_x as f64;
+    let v = y - cell_y as f64;
+    let weights = [
+        (cell_x, cell_y, (1.0 - u) * (1.0 - v)),
+        (cell_x + 1, cell_y, u * (1.0 - v)),
+        (cell_x, cell_y + 1, (1.0 - u) * v),
+        (cell_x + 1, cell_y + 1, u * v),
+    ];
+    for (x, y, weight) in weights {
+        let index = data_grid.index_of(x, y);
+        data_grid.values[index] += amount * weight;
+    }
+}
+
+/// Simulates water droplets running downhill, picking up material where they speed up and
+/// dropping it where they slow down or exceed their carrying capacity.
+pub fn hydraulic_erosion<R: Rng>(
+    heights: &DataGrid,
+    settings: &HydraulicErosionSettings,
+    random: &mut R,
+) -> HydraulicErosion {
+    println!("Simulating hydraulic erosion");
+    let mut heights = heights.clone();
+    let mut sediment_map = DataGrid::new(heights.width, heights.height);
+    let mut water_flow = DataGrid::new(heights.width, heights.height);
+    if heights.width < 2 || heights.height < 2 {
+        return HydraulicErosion {
+            heights,
+            sediment: sediment_map,
+            water_flow,
+        };
+    }
+    let max_x = (heights.width - 1) as f64;
+    let max_y = (heights.height - 1) as f64;
+
+    for _ in 0..settings.droplets {
+        let mut x = random.gen::<f64>() * max_x;
+        let mut y = random.gen::<f64>() * max_y;
+        let mut direction_x = 0.0;
+        let mut direction_y = 0.0;
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..settings.max_lifetime {
+            let (height, gradient_x, gradient_y) = height_and_gradient(&heights, x, y);
+            direction_x = direction_x * settings.inertia - gradient_x * (1.0 - settings.inertia);
+            direction_y = direction_y * settings.inertia - gradient_y * (1.0 - settings.inertia);
+            let length = f64::sqrt(direction_x * direction_x + direction_y * direction_y);
+            if length <= f64::EPSILON {
+                break;
+            }
+            direction_x /= length;
+            direction_y /= length;
+
+            let cell_index = heights.index_of(x as usize, y as usize);
+            water_flow.values[cell_index] += water;
+
+            let new_x = x + direction_x;
+            let new_y = y + direction_y;
+            if new_x < 0.0 || new_y < 0.0 || new_x >= max_x || new_y >= max_y {
+                break;
+            }
+
+            let (new_height, _, _) = height_and_gradient(&heights, new_x, new_y);
+            let delta_height = new_height - height;
+            let capacity =
+                f64::max(-delta_height, settings.min_slope) * speed * water * settings.capacity;
+
+            if sediment > capacity || delta_height > 0.0 {
+                let amount = if delta_height > 0.0 {
+                    f64::min(delta_height, sediment)
+                } else {
+                    (sediment - capacity) * settings.deposition
+                };
+                sediment -= amount;
+                distribute(&mut heights, x, y, amount);
+                distribute(&mut sediment_map, x, y, amount);
+            } else {
+                let amount = f64::min((capacity - sediment) * settings.erosion, -delta_height);
+                sediment += amount;
+                distribute(&mut heights, x, y, -amount);
+            }
+
+            speed = f64::sqrt(f64::max(
+                speed * speed - delta_height * settings.gravity,
+                0.0,
+            ));
+            water *= 1.0 - settings.evaporation;
+            x = new_x;
+            y = new_y;
+        }
+    }
+
+    HydraulicErosion {
+        heights,
+        sediment: sediment_map,
+        water_flow,
+    }
+}
+
+/// Moves material from each cell to its lower neighbours wherever the slope exceeds the talus.
+pub fn thermal_erosion(heights: &DataGrid, settings: &ThermalErosionSettings) -> DataGrid {
+    println!("Simulating thermal erosion");
+    let mut heights = heights.clone();
+    for _ in 0..settings.iterations {
+        let mut deltas = DataGrid::new(heights.width, heights.height);
+        for (x, y, height) in heights.iter_coords() {
+            let mut excesses = Vec::with_capacity(8);
+            for (neighbour_x, neighbour_y) in heights.neighbours(x, y, settings.neighbourhood) {
+                let distance = if neighbour_x != x && neighbour_y != y {
+                    std::f64::consts::SQRT_2
+                } else {
+                    1.0
+                };
+                let neighbour_height = heights.values[heights.index_of(neighbour_x, neighbour_y)];
+                let excess = height - neighbour_height - settings.talus * distance;
+                if excess > 0.0 {
+                    excesses.push((neighbour_x, neighbour_y, excess));
+                }
+            }
+            let total_excess: f64 = excesses.iter().map(|(_, _, excess)| excess).sum();
+            let max_excess = excesses
+                .iter()
+                .map(|(_, _, excess)| *excess)
+                .fold(0.0, f64::max);
+            if total_excess <= 0.0 {
+                continue;
+            }
+            let amount = settings.strength * max_excess * 0.5;
+            let index = deltas.index_of(x, y);
+            deltas.values[index] -= amount;
+            for (neighbour_x, neighbour_y, excess) in excesses {
+                let index = deltas.index_of(neighbour_x, neighbour_y);
+                deltas.values[index] += amount * excess / total_excess;
+            }
+        }
+        heights = heights + deltas;
+    }
+    heights
+}
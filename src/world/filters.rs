@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+use super::DataGrid;
+
+/// How filters read cells beyond the edges of the grid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeMode {
+    /// Repeats the nearest edge cell.
+    #[default]
+    Clamp,
+    /// Reads from the opposite edge, for tiling maps.
+    Wrap,
+    /// Reflects about the edge cell without repeating it.
+    Mirror,
+}
+
+impl EdgeMode {
+    fn resolve(&self, i: isize, length: usize) -> usize {
+        let length = length as isize;
+        let i = match self {
+            EdgeMode::Clamp => i.clamp(0, length - 1),
+            EdgeMode::Wrap => i.rem_euclid(length),
+            EdgeMode::Mirror if length == 1 => 0,
+            EdgeMode::Mirror => {
+                let period = 2 * (length - 1);
+                let i = i.rem_euclid(period);
+                if i < length {
+                    i
+                } else {
+                    period - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// First and second derivatives of the surface at a cell, by central differences.
+struct Derivatives {
+    p: f64,
+    q: f64,
+    r: f64,
+    s: f64,
+    t: f64,
+}
+
+impl DataGrid<f64> {
+    pub fn get_edge(&self, x: isize, y: isize, edge: EdgeMode) -> f64 {
+        let x = edge.resolve(x, self.width);
+        let y = edge.resolve(y, self.height);
+        self.values[self.index_of(x, y)]
+    }
+
+    /// Correlates the grid with a kernel of odd width and height, centred on each cell.
+    pub fn convolve(&self, kernel: &DataGrid, edge: EdgeMode) -> DataGrid {
+        let reach_x = (kernel.width / 2) as isize;
+        let reach_y = (kernel.height / 2) as isize;
+        self.map_coords(|x, y, _| {
+            kernel
+                .iter_coords()
+                .fold(0.0, |sum, (kernel_x, kernel_y, weight)| {
+                    let sample_x = x as isize + kernel_x as isize - reach_x;
+                    let sample_y = y as isize + kernel_y as isize - reach_y;
+                    sum + weight * self.get_edge(sample_x, sample_y, edge)
+                })
+        })
+    }
+
+    /// Applies the same one-dimensional kernel along rows and then along columns.
+    pub fn convolve_separable(&self, kernel: &[f64], edge: EdgeMode) -> DataGrid {
+        let reach = (kernel.len() / 2) as isize;
+        let rows = self.map_coords(|x, y, _| {
+            kernel.iter().enumerate().fold(0.0, |sum, (i, weight)| {
+                sum + weight * self.get_edge(x as isize + i as isize - reach, y as isize, edge)
+            })
+        });
+        rows.map_coords(|x, y, _| {
+            kernel.iter().enumerate().fold(0.0, |sum, (i, weight)| {
+                sum + weight * rows.get_edge(x as isize, y as isize + i as isize - reach, edge)
+            })
+        })
+    }
+
+    pub fn box_blur(&self, radius: usize, edge: EdgeMode) -> DataGrid {
+        let size = radius * 2 + 1;
+        self.convolve_separable(&vec![1.0 / size as f64; size], edge)
+    }
+
+    pub fn gaussian_blur(&self, sigma: f64, edge: EdgeMode) -> DataGrid {
+        if sigma <= 0.0 {
+            return self.clone();
+        }
+        let reach = (sigma * 3.0).ceil() as isize;
+        let mut kernel: Vec<f64> = (-reach..=reach)
+            .map(|i| f64::exp(-((i * i) as f64) / (2.0 * sigma * sigma)))
+            .collect();
+        let total: f64 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|weight| *weight /= total);
+        self.convolve_separable(&kernel, edge)
+    }
+
+    /// What is left after removing the detail a Gaussian blur keeps.
+    pub fn high_pass(&self, sigma: f64, edge: EdgeMode) -> DataGrid {
+        self - &self.gaussian_blur(sigma, edge)
+    }
+
+    /// Sobel gradient along x and y, scaled to the rise per cell.
+    pub fn sobel(&self, edge: EdgeMode) -> (DataGrid, DataGrid) {
+        let kernel_x =
+            DataGrid::from_vec(3, 3, vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0])
+                .map(|weight| weight / 8.0);
+        let kernel_y =
+            DataGrid::from_vec(3, 3, vec![-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0])
+                .map(|weight| weight / 8.0);
+        (
+            self.convolve(&kernel_x, edge),
+            self.convolve(&kernel_y, edge),
+        )
+    }
+
+    /// Steepness as rise per cell.
+    pub fn slope(&self, edge: EdgeMode) -> DataGrid {
+        let (dx, dy) = self.sobel(edge);
+        dx.zip_with(&dy, |dx, dy| f64::sqrt(dx * dx + dy * dy))
+    }
+
+    /// Downhill direction in radians, measured from the positive x axis towards positive y.
+    pub fn aspect(&self, edge: EdgeMode) -> DataGrid {
+        let (dx, dy) = self.sobel(edge);
+        dx.zip_with(&dy, |dx, dy| f64::atan2(-dy, -dx))
+    }
+
+    fn derivatives(&self, x: usize, y: usize, edge: EdgeMode) -> Derivatives {
+        let (x, y) = (x as isize, y as isize);
+        let z = |offset_x: isize, offset_y: isize| self.get_edge(x + offset_x, y + offset_y, edge);
+        Derivatives {
+            p: (z(1, 0) - z(-1, 0)) / 2.0,
+            q: (z(0, 1) - z(0, -1)) / 2.0,
+            r: z(1, 0) - 2.0 * z(0, 0) + z(-1, 0),
+            s: (z(1, 1) - z(1, -1) - z(-1, 1) + z(-1, -1)) / 4.0,
+            t: z(0, 1) - 2.0 * z(0, 0) + z(0, -1),
+        }
+    }
+
+    /// Curvature along the slope direction, positive where the ground rounds over convexly and
+    /// flow speeds up, and negative in concave stretches where it slows down. Flat cells have no
+    /// curvature.
+    pub fn profile_curvature(&self, edge: EdgeMode) -> DataGrid {
+        self.map_coords(|x, y, _| {
+            let Derivatives { p, q, r, s, t } = self.derivatives(x, y, edge);
+            let gradient = p * p + q * q;
+            if gradient <= f64::EPSILON {
+                return 0.0;
+            }
+            -(p * p * r + 2.0 * p * q * s + q * q * t) / (gradient * (1.0 + gradient).powf(1.5))
+        })
+    }
+
+    /// Curvature of the contour lines, positive on spurs where flow spreads out and negative in
+    /// hollows where it converges.
+    pub fn plan_curvature(&self, edge: EdgeMode) -> DataGrid {
+        self.map_coords(|x, y, _| {
+            let Derivatives { p, q, r, s, t } = self.derivatives(x, y, edge);
+            let gradient = p * p + q * q;
+            if gradient <= f64::EPSILON {
+                return 0.0;
+            }
+            -(q * q * r - 2.0 * p * q * s + p * p * t) / gradient.powf(1.5)
+        })
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{data_grid::Neighbourhood, DataGrid};
+
+/// Places a grid in map coordinates. Rows run from north to south, as in both ESRI grids and
+/// GeoTIFF.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoReference {
+    /// Map coordinates of the outer corner of the bottom-left cell.
+    pub lower_left: [f64; 2],
+    pub cell_size: f64,
+    /// Value that marks cells without data. Such cells keep this value when read, so fill them
+    /// with `DataGrid::fill_no_data` before resampling or blending.
+    pub no_data: Option<f64>,
+    /// EPSG code of the projected coordinate system. Only GeoTIFF files carry it.
+    pub epsg: Option<u16>,
+}
+
+impl Default for GeoReference {
+    fn default() -> Self {
+        Self {
+            lower_left: [0.0, 0.0],
+            cell_size: 1.0,
+            no_data: None,
+            epsg: None,
+        }
+    }
+}
+
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_ASCII: u16 = 2;
+const TIFF_DOUBLE: u16 = 12;
+
+enum TagValue {
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Double(Vec<f64>),
+    Ascii(String),
+}
+
+impl TagValue {
+    fn field_type(&self) -> u16 {
+        match self {
+            TagValue::Short(_) => TIFF_SHORT,
+            TagValue::Long(_) => TIFF_LONG,
+            TagValue::Double(_) => TIFF_DOUBLE,
+            TagValue::Ascii(_) => TIFF_ASCII,
+        }
+    }
+    fn count(&self) -> u32 {
+        match self {
+            TagValue::Short(values) => values.len() as u32,
+            TagValue::Long(values) => values.len() as u32,
+            TagValue::Double(values) => values.len() as u32,
+            TagValue::Ascii(text) => text.len() as u32 + 1,
+        }
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            TagValue::Short(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            TagValue::Long(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            TagValue::Double(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            TagValue::Ascii(text) => text.bytes().chain([0]).collect(),
+        }
+    }
+}
+
+/// Little-endian TIFF with a single strip, writing values that do not fit in their directory
+/// entry ahead of the directory itself.
+fn encode_tiff(data: &[u8], mut tags: Vec<(u16, TagValue)>) -> Vec<u8> {
+    tags.sort_by_key(|(tag, _)| *tag);
+    let mut bytes = vec![b'I', b'I', 42, 0, 0, 0, 0, 0];
+    bytes.extend_from_slice(data);
+    let mut entries = Vec::new();
+    for (tag, value) in &tags {
+        let mut value_bytes = value.to_bytes();
+        let field = if value_bytes.len() <= 4 {
+            value_bytes.resize(4, 0);
+            value_bytes
+        } else {
+            if !bytes.len().is_multiple_of(2) {
+                bytes.push(0);
+            }
+            let offset = bytes.len() as u32;
+            bytes.extend_from_slice(&value_bytes);
+            offset.to_le_bytes().to_vec()
+        };
+        entries.push((*tag, value.field_type(), value.count(), field));
+    }
+    if !bytes.len().is_multiple_of(2) {
+        bytes.push(0);
+    }
+    let directory_offset = bytes.len() as u32;
+    bytes[4..8].copy_from_slice(&directory_offset.to_le_bytes());
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, field_type, count, field) in entries {
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&field_type.to_le_bytes());
+        bytes.extend_from_slice(&count.to_le_bytes());
+        bytes.extend_from_slice(&field);
+    }
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes
+}
+
+impl DataGrid<f64> {
+    /// ESRI ASCII grid, with values written at full precision.
+    pub fn to_ascii_grid(&self, reference: &GeoReference) -> String {
+        let mut text = format!(
+            "ncols {}\nnrows {}\nxllcorner {}\nyllcorner {}\ncellsize {}\n",
+            self.width,
+            self.height,
+            reference.lower_left[0],
+            reference.lower_left[1],
+            reference.cell_size
+        );
+        if let Some(no_data) = reference.no_data {
+            text += &format!("NODATA_value {}\n", no_data);
+        }
+        for row in self.values.chunks(self.width.max(1)) {
+            let row: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            text += &row.join(" ");
+            text.push('\n');
+        }
+        text
+    }
+    /// Reads an ESRI ASCII grid. Origins given by cell centre are moved to the cell corner.
+    pub fn from_ascii_grid(text: &str) -> (Self, GeoReference) {
+        let mut reference = GeoReference::default();
+        let (mut width, mut height) = (None, None);
+        let mut centre = [false, false];
+        let mut tokens = text.split_whitespace().peekable();
+        while let Some(key) = tokens.peek() {
+            if key.parse::<f64>().is_ok() {
+                break;
+            }
+            let key = tokens.next().unwrap().to_lowercase();
+            let value = tokens
+                .next()
+                .expect("Could not read ESRI grid header value");
+            let number = || {
+                value
+                    .parse::<f64>()
+                    .expect("Could not parse ESRI grid header value")
+            };
+            match key.as_str() {
+                "ncols" => width = Some(number() as usize),
+                "nrows" => height = Some(number() as usize),
+                "xllcorner" => reference.lower_left[0] = number(),
+                "yllcorner" => reference.lower_left[1] = number(),
+                "xllcenter" => (reference.lower_left[0], centre[0]) = (number(), true),
+                "yllcenter" => (reference.lower_left[1], centre[1]) = (number(), true),
+                "cellsize" => reference.cell_size = number(),
+                "nodata_value" => reference.no_data = Some(number()),
+                _ => panic!("Unknown ESRI grid header key '{}'", key),
+            }
+        }
+        for (corner, centre) in reference.lower_left.iter_mut().zip(centre) {
+            if centre {
+                *corner -= reference.cell_size / 2.0;
+            }
+        }
+        let values = tokens
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .expect("Could not parse ESRI grid value")
+            })
+            .collect();
+        let data_grid = Self::from_vec(
+            width.expect("ESRI grid header has no ncols"),
+            height.expect("ESRI grid header has no nrows"),
+            values,
+        );
+        (data_grid, reference)
+    }
+    /// Replaces cells holding `no_data` with `fill`, or with the value of the nearest cell that
+    /// has data when no fill is given.
+    pub fn fill_no_data(&self, no_data: f64, fill: Option<f64>) -> Self {
+        let is_no_data = |value: f64| value == no_data || (no_data.is_nan() && value.is_nan());
+        if let Some(fill) = fill {
+            return self.map(|value| if is_no_data(value) { fill } else { value });
+        }
+        let mut filled = self.clone();
+        let mut known = self.map(|value| !is_no_data(value));
+        let mut queue: VecDeque<usize> = (0..self.len()).filter(|i| known.values[*i]).collect();
+        if queue.is_empty() && !self.is_empty() {
+            panic!("Could not fill grid, every cell is no data");
+        }
+        while let Some(index) = queue.pop_front() {
+            let (x, y) = self.coords_of(index);
+            for (offset_x, offset_y) in Neighbourhood::Eight.offsets() {
+                let neighbour_x = x as isize + offset_x;
+                let neighbour_y = y as isize + offset_y;
+                if neighbour_x < 0 || neighbour_y < 0 {
+                    continue;
+                }
+                let (neighbour_x, neighbour_y) = (neighbour_x as usize, neighbour_y as usize);
+                if !self.contains(neighbour_x, neighbour_y) {
+                    continue;
+                }
+                let neighbour = self.index_of(neighbour_x, neighbour_y);
+                if !known.values[neighbour] {
+                    known.values[neighbour] = true;
+                    filled.values[neighbour] = filled.values[index];
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        filled
+    }
+    pub fn save_ascii_grid<P: AsRef<Path>>(&self, path: P, reference: &GeoReference) {
+        fs::write(path, self.to_ascii_grid(reference)).expect("Could not write ESRI grid");
+    }
+    pub fn load_ascii_grid<P: AsRef<Path>>(path: P) -> (Self, GeoReference) {
+        let text = fs::read_to_string(path).expect("Could not read ESRI grid");
+        Self::from_ascii_grid(&text)
+    }
+
+    /// Single-band 32-bit float GeoTIFF, with the origin and cell size as a model tiepoint and
+    /// pixel scale.
+    pub fn to_geotiff(&self, reference: &GeoReference) -> Vec<u8> {
+        let data: Vec<u8> = self
+            .values
+            .iter()
+            .flat_map(|value| (*value as f32).to_le_bytes())
+            .collect();
+        let top = reference.lower_left[1] + self.height as f64 * reference.cell_size;
+        let mut geo_keys = vec![
+            // GTModelTypeGeoKey, projected
+            [1024, 0, 1, 1],
+            // GTRasterTypeGeoKey, pixel is area
+            [1025, 0, 1, 1],
+        ];
+        if let Some(epsg) = reference.epsg {
+            // ProjectedCSTypeGeoKey
+            geo_keys.push([3072, 0, 1, epsg]);
+        }
+        let mut directory = vec![1, 1, 0, geo_keys.len() as u16];
+        directory.extend(geo_keys.concat());
+
+        let mut tags = vec![
+            (256, TagValue::Long(vec![self.width as u32])),
+            (257, TagValue::Long(vec![self.height as u32])),
+            (258, TagValue::Short(vec![32])),
+            // No compression
+            (259, TagValue::Short(vec![1])),
+            // Black is zero
+            (262, TagValue::Short(vec![1])),
+            (273, TagValue::Long(vec![8])),
+            (277, TagValue::Short(vec![1])),
+            (278, TagValue::Long(vec![self.height as u32])),
+            (279, TagValue::Long(vec![data.len() as u32])),
+            (284, TagValue::Short(vec![1])),
+            // Floating point samples
+            (339, TagValue::Short(vec![3])),
+            (
+                33550,
+                TagValue::Double(vec![reference.cell_size, reference.cell_size, 0.0]),
+            ),
+            (
+                33922,
+                TagValue::Double(vec![0.0, 0.0, 0.0, reference.lower_left[0], top, 0.0]),
+            ),
+            (34735, TagValue::Short(directory)),
+        ];
+        if let Some(no_data) = reference.no_data {
+            // GDAL_NODATA
+            tags.push((42113, TagValue::Ascii(no_data.to_string())));
+        }
+        encode_tiff(&data, tags)
+    }
+    pub fn save_geotiff<P: AsRef<Path>>(&self, path: P, reference: &GeoReference) {
+        fs::write(path, self.to_geotiff(reference)).expect("Could not write GeoTIFF");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_grid_round_trips_exactly() {
+        let grid = DataGrid::from_fn(9, 5, |x, y| (x as f64 * 0.1 - y as f64 / 3.0).exp());
+        let reference = GeoReference {
+            lower_left: [412_345.5, 5_678_901.25],
+            cell_size: 30.0,
+            no_data: Some(-9999.0),
+            epsg: None,
+        };
+        let (read, read_reference) = DataGrid::from_ascii_grid(&grid.to_ascii_grid(&reference));
+        assert_eq!(read, grid);
+        assert_eq!(read_reference, reference);
+    }
+}
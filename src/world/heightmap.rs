@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::Path;
+
+use image::{ImageBuffer, Luma, Rgb, Rgb32FImage};
+use serde::{Deserialize, Serialize};
+
+use super::DataGrid;
+
+pub type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// Grid values that map to the bottom and top of an export format. Values outside are clamped
+/// by integer formats and extrapolated by float formats.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ValueRange {
+    /// Leaves values in `[0, 1]` as they are.
+    pub const UNIT: ValueRange = ValueRange { min: 0.0, max: 1.0 };
+
+    /// The range actually covered by a grid, so integer formats use their full precision.
+    pub fn of(data_grid: &DataGrid) -> Self {
+        Self {
+            min: data_grid.min_value(),
+            max: data_grid.max_value(),
+        }
+    }
+    pub fn normalise(&self, value: f64) -> f64 {
+        if self.max == self.min {
+            return 0.0;
+        }
+        (value - self.min) / (self.max - self.min)
+    }
+    pub fn denormalise(&self, value: f64) -> f64 {
+        self.min + value * (self.max - self.min)
+    }
+}
+
+/// Headerless little-endian formats, one sample per cell in row order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawFormat {
+    R16,
+    R32F,
+}
+
+impl RawFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            RawFormat::R16 => 2,
+            RawFormat::R32F => 4,
+        }
+    }
+}
+
+fn to_u16(value: f64) -> u16 {
+    (value * u16::MAX as f64)
+        .round()
+        .clamp(0.0, u16::MAX as f64) as u16
+}
+
+fn from_u16(value: u16) -> f64 {
+    value as f64 / u16::MAX as f64
+}
+
+/// Exports and imports with more precision than `to_image`. Integer formats quantise to the
+/// steps of their range, so exporting an imported grid again writes the same samples.
+impl DataGrid<f64> {
+    pub fn to_image_16(&self, range: ValueRange) -> Gray16Image {
+        Gray16Image::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let value = self.values[self.index_of(x as usize, y as usize)];
+            Luma([to_u16(range.normalise(value))])
+        })
+    }
+    pub fn from_image_16(image: &Gray16Image, range: ValueRange) -> Self {
+        Self::from_fn(image.width() as usize, image.height() as usize, |x, y| {
+            range.denormalise(from_u16(image.get_pixel(x as u32, y as u32)[0]))
+        })
+    }
+    pub fn save_png_16<P: AsRef<Path>>(&self, path: P, range: ValueRange) {
+        self.to_image_16(range)
+            .save_with_format(path, image::ImageFormat::Png)
+            .expect("Could not write 16-bit PNG");
+    }
+    pub fn load_png_16<P: AsRef<Path>>(path: P, range: ValueRange) -> Self {
+        let image = image::open(path)
+            .expect("Could not read 16-bit PNG")
+            .into_luma16();
+        Self::from_image_16(&image, range)
+    }
+
+    pub fn to_raw(&self, format: RawFormat, range: ValueRange) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len() * format.bytes_per_sample());
+        for value in self.iter() {
+            let value = range.normalise(*value);
+            match format {
+                RawFormat::R16 => bytes.extend_from_slice(&to_u16(value).to_le_bytes()),
+                RawFormat::R32F => bytes.extend_from_slice(&(value as f32).to_le_bytes()),
+            }
+        }
+        bytes
+    }
+    pub fn from_raw(
+        bytes: &[u8],
+        width: usize,
+        height: usize,
+        format: RawFormat,
+        range: ValueRange,
+    ) -> Self {
+        assert_eq!(
+            bytes.len(),
+            width * height * format.bytes_per_sample(),
+            "Raw heightmap size does not match dimensions"
+        );
+        let values = bytes
+            .chunks_exact(format.bytes_per_sample())
+            .map(|sample| {
+                let value = match format {
+                    RawFormat::R16 => from_u16(u16::from_le_bytes([sample[0], sample[1]])),
+                    RawFormat::R32F => {
+                        f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64
+                    }
+                };
+                range.denormalise(value)
+            })
+            .collect();
+        Self::from_vec(width, height, values)
+    }
+    pub fn save_raw<P: AsRef<Path>>(&self, path: P, format: RawFormat, range: ValueRange) {
+        fs::write(path, self.to_raw(format, range)).expect("Could not write raw heightmap");
+    }
+    /// Raw files carry no header, so the dimensions must be known up front.
+    pub fn load_raw<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        format: RawFormat,
+        range: ValueRange,
+    ) -> Self {
+        let bytes = fs::read(path).expect("Could not read raw heightmap");
+        Self::from_raw(&bytes, width, height, format, range)
+    }
+
+    /// EXR images are written with the value in every channel, since the encoder only takes
+    /// RGB. Samples are 32-bit floats.
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P, range: ValueRange) {
+        let image = Rgb32FImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let value = range.normalise(self.values[self.index_of(x as usize, y as usize)]);
+            Rgb([value as f32; 3])
+        });
+        image
+            .save_with_format(path, image::ImageFormat::OpenExr)
+            .expect("Could not write EXR heightmap");
+    }
+    pub fn load_exr<P: AsRef<Path>>(path: P, range: ValueRange) -> Self {
+        let image = image::open(path)
+            .expect("Could not read EXR heightmap")
+            .into_rgb32f();
+        Self::from_fn(image.width() as usize, image.height() as usize, |x, y| {
+            range.denormalise(image.get_pixel(x as u32, y as u32)[0] as f64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Values a 32-bit float holds exactly, spread over `[0, 1]`.
+    fn test_grid() -> DataGrid {
+        DataGrid::from_fn(13, 7, |x, y| ((x * 7 + y * 13) % 64) as f64 / 64.0)
+    }
+
+    #[test]
+    fn r32f_round_trips_exactly() {
+        let grid = test_grid();
+        let bytes = grid.to_raw(RawFormat::R32F, ValueRange::UNIT);
+        let read = DataGrid::from_raw(&bytes, 13, 7, RawFormat::R32F, ValueRange::UNIT);
+        assert_eq!(read, grid);
+    }
+
+    #[test]
+    fn exr_round_trips_exactly() {
+        let grid = test_grid();
+        let path = env::temp_dir().join(format!("heightmap_test_{}.exr", std::process::id()));
+        grid.save_exr(&path, ValueRange::UNIT);
+        let read = DataGrid::load_exr(&path, ValueRange::UNIT);
+        fs::remove_file(&path).expect("Could not remove test EXR");
+        assert_eq!(read, grid);
+    }
+
+    #[test]
+    fn png_16_round_trips_within_a_step() {
+        let range = ValueRange {
+            min: -120.0,
+            max: 880.0,
+        };
+        let grid = DataGrid::from_fn(13, 7, |x, y| {
+            -120.0 + ((x * 31 + y * 17) % 271) as f64 * 3.7
+        });
+        let path = env::temp_dir().join(format!("heightmap_test_{}.png", std::process::id()));
+        grid.save_png_16(&path, range);
+        let read = DataGrid::load_png_16(&path, range);
+        fs::remove_file(&path).expect("Could not remove test PNG");
+        let step = (range.max - range.min) / u16::MAX as f64;
+        for (read, value) in read.iter().zip(grid.iter()) {
+            assert!(
+                (read - value).abs() <= step,
+                "{} read back as {}",
+                value,
+                read
+            );
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use super::{RegionGraph, River, WorldConfig};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KingdomSettings {
+    pub count: usize,
+    /// Minimum distance in cells between two capitals.
+    pub capital_spacing: f64,
+    /// Extra cost per unit of height above sea level, so ranges tend to become borders.
+    pub mountain_cost: f64,
+    /// Multiplier on the cost of expanding into a cell that is mostly water.
+    pub water_cost: f64,
+    /// Cost added when the border between two cells is crossed by a river.
+    pub river_cost: f64,
+}
+
+impl Default for KingdomSettings {
+    fn default() -> Self {
+        Self {
+            count: 8,
+            capital_spacing: 150.0,
+            mountain_cost: 20.0,
+            water_cost: 4.0,
+            river_cost: 40.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Kingdom {
+    pub id: usize,
+    /// Region cell holding the capital.
+    pub capital: usize,
+    pub regions: Vec<usize>,
+    pub area: usize,
+    /// Land area weighted towards low ground, as a stand-in for population.
+    pub population: f64,
+    pub coastline_length: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Border {
+    pub kingdoms: (usize, usize),
+    pub points: Vec<[f64; 2]>,
+}
+
+pub struct Partition {
+    pub region_kingdoms: Vec<Option<usize>>,
+    pub kingdoms: Vec<Kingdom>,
+    pub borders: Vec<Border>,
+}
+
+struct Frontier {
+    cost: f64,
+    region: usize,
+    kingdom: usize,
+}
+
+impl PartialEq for Frontier {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Frontier {}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.region.cmp(&self.region))
+    }
+}
+
+fn length(a: [f64; 2], b: [f64; 2]) -> f64 {
+    f64::sqrt((a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1]))
+}
+
+/// Pairs of neighbouring regions, smallest id first, whose shared border a river flows across.
+fn river_crossings(regions: &RegionGraph, rivers: &[River]) -> HashSet<(usize, usize)> {
+    let mut crossings = HashSet::new();
+    for river in rivers {
+        let cells = river
+            .points
+            .iter()
+            .filter_map(|point| regions.cell_ids.get(point[0] as usize, point[1] as usize));
+        let mut previous = None;
+        for cell in cells {
+            let cell = cell as usize;
+            if let Some(previous) = previous.filter(|previous| *previous != cell) {
+                crossings.insert((usize::min(previous, cell), usize::max(previous, cell)));
+            }
+            previous = Some(cell);
+        }
+    }
+    crossings
+}
+
+fn pick_capitals(
+    config: &WorldConfig,
+    regions: &RegionGraph,
+    settings: &KingdomSettings,
+) -> Vec<usize> {
+    let mut candidates: Vec<usize> = regions
+        .cells
+        .iter()
+        .filter(|cell| cell.is_land())
+        .map(|cell| cell.id)
+        .collect();
+    candidates.shuffle(&mut config.rng("kingdoms"));
+    let mut capitals: Vec<usize> = Vec::new();
+    for candidate in candidates {
+        if capitals.len() >= settings.count {
+            break;
+        }
+        let site = regions.cells[candidate].site;
+        if capitals
+            .iter()
+            .all(|capital| length(regions.cells[*capital].site, site) >= settings.capital_spacing)
+        {
+            capitals.push(candidate);
+        }
+    }
+    capitals
+}
+
+fn point_key(point: [f64; 2]) -> (i64, i64) {
+    (
+        (point[0] * 1e4).round() as i64,
+        (point[1] * 1e4).round() as i64,
+    )
+}
+
+/// Joins border segments that share an end point into polylines.
+fn join_segments(segments: Vec<[[f64; 2]; 2]>) -> Vec<Vec<[f64; 2]>> {
+    let mut point_segments: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        for point in segment {
+            point_segments.entry(point_key(*point)).or_default().push(i);
+        }
+    }
+    let mut starts: Vec<[f64; 2]> = segments
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|point| point_segments[&point_key(*point)].len() == 1)
+        .collect();
+    starts.extend(segments.iter().map(|segment| segment[0]));
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = Vec::new();
+    for start in starts {
+        let mut point = start;
+        let mut line = Vec::new();
+        while let Some(segment) = point_segments[&point_key(point)]
+            .iter()
+            .copied()
+            .find(|segment| !used[*segment])
+        {
+            used[segment] = true;
+            if line.is_empty() {
+                line.push(point);
+            }
+            let [a, b] = segments[segment];
+            point = if point_key(a) == point_key(point) {
+                b
+            } else {
+                a
+            };
+            line.push(point);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Grows kingdoms outwards from their capitals with a multi-source Dijkstra search over the
+/// region graph. Every region goes to the kingdom that reaches it most cheaply, and water
+/// regions can be crossed but are never owned.
+pub fn grow_kingdoms(
+    config: &WorldConfig,
+    sea_level: f64,
+    regions: &RegionGraph,
+    rivers: &[River],
+    settings: &KingdomSettings,
+) -> Partition {
+    println!("Growing kingdoms");
+    let capitals = pick_capitals(config, regions, settings);
+    let crossings = river_crossings(regions, rivers);
+
+    let mut costs = vec![f64::INFINITY; regions.cells.len()];
+    let mut owners = vec![None; regions.cells.len()];
+    let mut open = BinaryHeap::new();
+    for (kingdom, capital) in capitals.iter().enumerate() {
+        costs[*capital] = 0.0;
+        open.push(Frontier {
+            cost: 0.0,
+            region: *capital,
+            kingdom,
+        });
+    }
+    while let Some(frontier) = open.pop() {
+        if owners[frontier.region].is_some() {
+            continue;
+        }
+        owners[frontier.region] = Some(frontier.kingdom);
+        let cell = &regions.cells[frontier.region];
+        for neighbour in &cell.neighbours {
+            let next = &regions.cells[*neighbour];
+            if owners[next.id].is_some() {
+                continue;
+            }
+            let elevation = f64::max(next.mean_height - sea_level, 0.0);
+            let mut step =
+                length(cell.site, next.site) * (1.0 + elevation * settings.mountain_cost);
+            if !next.is_land() {
+                step *= settings.water_cost;
+            }
+            if crossings.contains(&(usize::min(cell.id, next.id), usize::max(cell.id, next.id))) {
+                step += settings.river_cost;
+            }
+            let cost = frontier.cost + step;
+            if cost < costs[next.id] {
+                costs[next.id] = cost;
+                open.push(Frontier {
+                    cost,
+                    region: next.id,
+                    kingdom: frontier.kingdom,
+                });
+            }
+        }
+    }
+    let region_kingdoms: Vec<Option<usize>> = regions
+        .cells
+        .iter()
+        .map(|cell| owners[cell.id].filter(|_| cell.is_land()))
+        .collect();
+
+    let mut kingdoms: Vec<Kingdom> = capitals
+        .iter()
+        .enumerate()
+        .map(|(id, capital)| Kingdom {
+            id,
+            capital: *capital,
+            regions: Vec::new(),
+            area: 0,
+            population: 0.0,
+            coastline_length: 0.0,
+        })
+        .collect();
+    let mut border_segments: HashMap<(usize, usize), Vec<[[f64; 2]; 2]>> = HashMap::new();
+    for cell in &regions.cells {
+        let kingdom = match region_kingdoms[cell.id] {
+            Some(kingdom) => &mut kingdoms[kingdom],
+            None => continue,
+        };
+        let land_area = cell.area as f64 * cell.land_fraction;
+        kingdom.regions.push(cell.id);
+        kingdom.area += cell.area;
+        kingdom.population += land_area
+            / (1.0 + f64::max(cell.mean_height - sea_level, 0.0) * settings.mountain_cost);
+        for neighbour in &cell.neighbours {
+            let edge = match regions.shared_edge(cell.id, *neighbour) {
+                Some(edge) => edge,
+                None => continue,
+            };
+            if !regions.cells[*neighbour].is_land() {
+                kingdom.coastline_length += length(edge[0], edge[1]);
+                continue;
+            }
+            match region_kingdoms[*neighbour] {
+                Some(other) if other > kingdom.id => border_segments
+                    .entry((kingdom.id, other))
+                    .or_default()
+                    .push(edge),
+                _ => {}
+            }
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = border_segments.keys().copied().collect();
+    pairs.sort_unstable();
+    let mut borders = Vec::new();
+    for pair in pairs {
+        let segments = border_segments.remove(&pair).unwrap();
+        for points in join_segments(segments) {
+            borders.push(Border {
+                kingdoms: pair,
+                points,
+            });
+        }
+    }
+
+    Partition {
+        region_kingdoms,
+        kingdoms,
+        borders,
+    }
+}
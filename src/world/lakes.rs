@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{data_grid::Neighbourhood, DataGrid};
+
+pub const NO_LAKE: u32 = 0;
+pub const DRAIN_EPSILON: f64 = 1e-10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepressionMode {
+    Fill,
+    Lakes,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DepressionSettings {
+    pub mode: DepressionMode,
+    /// Basins smaller than this many cells are filled instead of becoming lakes.
+    pub min_area: usize,
+    /// Basins shallower than this are filled instead of becoming lakes.
+    pub min_depth: f64,
+}
+
+impl Default for DepressionSettings {
+    fn default() -> Self {
+        Self {
+            mode: DepressionMode::Lakes,
+            min_area: 64,
+            min_depth: 0.005,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lake {
+    pub id: u32,
+    pub surface_level: f64,
+    pub area: usize,
+    pub depth: f64,
+    pub spill_point: (usize, usize),
+}
+
+pub struct Depressions {
+    pub heights: DataGrid,
+    pub labels: DataGrid<u32>,
+    pub lakes: Vec<Lake>,
+}
+
+struct FloodCell {
+    height: f64,
+    index: usize,
+}
+
+impl PartialEq for FloodCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodCell {}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+/// Priority-flood from the map edges and the sea. Every cell ends up at least `epsilon` above
+/// the cell it drains into, so an epsilon of zero gives flat lake surfaces and a small positive
+/// epsilon gives a surface where every cell has a downhill path to the sea or off the map.
+pub fn fill_depressions(heights: &DataGrid, sea_level: f64, epsilon: f64) -> DataGrid {
+    let mut filled = heights.clone();
+    let mut closed = DataGrid::<bool>::new(heights.width, heights.height);
+    let mut open = BinaryHeap::new();
+    for (x, y) in heights.coords() {
+        let index = heights.index_of(x, y);
+        if x == 0
+            || y == 0
+            || x == heights.width - 1
+            || y == heights.height - 1
+            || heights.values[index] < sea_level
+        {
+            closed.values[index] = true;
+            open.push(FloodCell {
+                height: filled.values[index],
+                index,
+            });
+        }
+    }
+    while let Some(cell) = open.pop() {
+        let (x, y) = heights.coords_of(cell.index);
+        for (neighbour_x, neighbour_y) in heights.neighbours(x, y, Neighbourhood::Eight) {
+            let index = heights.index_of(neighbour_x, neighbour_y);
+            if closed.values[index] {
+                continue;
+            }
+            closed.values[index] = true;
+            filled.values[index] = f64::max(filled.values[index], cell.height + epsilon);
+            open.push(FloodCell {
+                height: filled.values[index],
+                index,
+            });
+        }
+    }
+    filled
+}
+
+/// Fills or turns into lakes the basins on land. Basins on the sea floor are left as they are.
+pub fn find_depressions(
+    heights: &DataGrid,
+    sea_level: f64,
+    settings: &DepressionSettings,
+) -> Depressions {
+    println!("Finding depressions");
+    if settings.mode == DepressionMode::Fill {
+        return Depressions {
+            heights: fill_depressions(heights, sea_level, DRAIN_EPSILON),
+            labels: DataGrid::new(heights.width, heights.height),
+            lakes: Vec::new(),
+        };
+    }
+
+    let filled = fill_depressions(heights, sea_level, 0.0);
+    let mut output_heights = heights.clone();
+    let mut labels = DataGrid::<u32>::new(heights.width, heights.height);
+    let mut visited = DataGrid::<bool>::new(heights.width, heights.height);
+    let mut lakes = Vec::new();
+    for start in 0..heights.len() {
+        if visited.values[start] || filled.values[start] <= heights.values[start] {
+            continue;
+        }
+        let surface_level = filled.values[start];
+        let mut cells = Vec::new();
+        let mut stack = vec![start];
+        visited.values[start] = true;
+        while let Some(index) = stack.pop() {
+            cells.push(index);
+            let (x, y) = heights.coords_of(index);
+            for (neighbour_x, neighbour_y) in heights.neighbours(x, y, Neighbourhood::Eight) {
+                let neighbour = heights.index_of(neighbour_x, neighbour_y);
+                if !visited.values[neighbour]
+                    && filled.values[neighbour] > heights.values[neighbour]
+                {
+                    visited.values[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        let depth = cells
+            .iter()
+            .map(|index| surface_level - heights.values[*index])
+            .fold(0.0, f64::max);
+        if cells.len() < settings.min_area || depth < settings.min_depth {
+            for index in cells {
+                output_heights.values[index] = surface_level;
+            }
+            continue;
+        }
+
+        let id = lakes.len() as u32 + 1;
+        for index in &cells {
+            labels.values[*index] = id;
+        }
+        let spill_point = cells
+            .iter()
+            .flat_map(|index| {
+                let (x, y) = heights.coords_of(*index);
+                heights.neighbours(x, y, Neighbourhood::Eight)
+            })
+            .filter(|(x, y)| labels.get(*x, *y) != Some(id))
+            .min_by(|a, b| {
+                let a = heights.get(a.0, a.1).unwrap();
+                let b = heights.get(b.0, b.1).unwrap();
+                a.total_cmp(&b)
+            })
+            .unwrap_or_else(|| heights.coords_of(cells[0]));
+        lakes.push(Lake {
+            id,
+            surface_level,
+            area: cells.len(),
+            depth,
+            spill_point,
+        });
+    }
+    Depressions {
+        heights: output_heights,
+        labels,
+        lakes,
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use nalgebra_glm::smoothstep;
+use noise::{
+    BasicMulti, Billow, Fbm, HybridMulti, MultiFractal, NoiseFn, OpenSimplex, Perlin, Seedable,
+    Select, SuperSimplex, Terrace, Turbulence, Value, Worley,
+};
+use serde::Deserialize;
+
+use super::{get_noise, BlendMode, DataGrid, WorldConfig};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LayerGraph {
+    pub output: String,
+    pub nodes: HashMap<String, LayerNode>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LayerNode {
+    Noise {
+        source: NoiseSource,
+        #[serde(default = "default_one")]
+        frequency: f64,
+        #[serde(default)]
+        skew: f64,
+        #[serde(default = "default_one")]
+        stretch: f64,
+        #[serde(default)]
+        seed_offset: u32,
+    },
+    Constant {
+        value: f64,
+    },
+    /// ESRI ASCII grid, such as a DEM exported from a GIS, resampled to the world size. Cells
+    /// without data take `fill`, or the value of the nearest cell with data, before resampling.
+    AsciiGrid {
+        path: String,
+        #[serde(default)]
+        fill: Option<f64>,
+    },
+    Remap {
+        input: String,
+        min: f64,
+        max: f64,
+    },
+    Add {
+        inputs: Vec<String>,
+    },
+    Multiply {
+        inputs: Vec<String>,
+    },
+    Mask {
+        input: String,
+        mask: String,
+        #[serde(default)]
+        background: f64,
+    },
+    Smoothstep {
+        input: String,
+        edge_0: f64,
+        edge_1: f64,
+    },
+    Blend {
+        bottom: String,
+        top: String,
+        #[serde(default)]
+        mode: BlendMode,
+        #[serde(default = "default_one")]
+        opacity: f64,
+        mask: Option<String>,
+    },
+    Terrace {
+        input: String,
+        control_points: Vec<f64>,
+        #[serde(default)]
+        invert: bool,
+    },
+    Select {
+        low: String,
+        high: String,
+        control: String,
+        bounds: [f64; 2],
+        #[serde(default)]
+        falloff: f64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NoiseSource {
+    Perlin,
+    Value,
+    OpenSimplex,
+    SuperSimplex,
+    Worley {
+        #[serde(default = "default_one")]
+        displacement: f64,
+        #[serde(default)]
+        range: bool,
+    },
+    Fbm(FractalSettings),
+    Billow(FractalSettings),
+    BasicMulti(FractalSettings),
+    HybridMulti(FractalSettings),
+    Turbulence {
+        #[serde(default = "default_one")]
+        frequency: f64,
+        #[serde(default = "default_one")]
+        power: f64,
+        #[serde(default = "default_roughness")]
+        roughness: usize,
+    },
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FractalSettings {
+    pub octaves: Option<usize>,
+    pub lacunarity: Option<f64>,
+    pub persistence: Option<f64>,
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_roughness() -> usize {
+    Turbulence::<Perlin>::DEFAULT_ROUGHNESS
+}
+
+impl FractalSettings {
+    fn apply<N: MultiFractal>(&self, mut noise: N) -> N {
+        if let Some(octaves) = self.octaves {
+            noise = noise.set_octaves(octaves);
+        }
+        if let Some(lacunarity) = self.lacunarity {
+            noise = noise.set_lacunarity(lacunarity);
+        }
+        if let Some(persistence) = self.persistence {
+            noise = noise.set_persistence(persistence);
+        }
+        noise
+    }
+}
+
+impl NoiseSource {
+    fn sample(
+        &self,
+        config: &WorldConfig,
+        seed: u32,
+        frequency: f64,
+        skew: f64,
+        stretch: f64,
+    ) -> DataGrid {
+        match self {
+            NoiseSource::Perlin => get_noise(
+                config,
+                Perlin::new().set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::Value => get_noise(
+                config,
+                Value::new().set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::OpenSimplex => get_noise(
+                config,
+                OpenSimplex::new().set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::SuperSimplex => get_noise(
+                config,
+                SuperSimplex::new().set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::Worley {
+                displacement,
+                range,
+            } => get_noise(
+                config,
+                Worley::new()
+                    .set_seed(seed)
+                    .set_displacement(*displacement)
+                    .enable_range(*range),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::Fbm(settings) => get_noise(
+                config,
+                settings.apply(Fbm::new()).set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::Billow(settings) => get_noise(
+                config,
+                settings.apply(Billow::new()).set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::BasicMulti(settings) => get_noise(
+                config,
+                settings.apply(BasicMulti::new()).set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::HybridMulti(settings) => get_noise(
+                config,
+                settings.apply(HybridMulti::new()).set_seed(seed),
+                frequency,
+                skew,
+                stretch,
+            ),
+            NoiseSource::Turbulence {
+                frequency: turbulence_frequency,
+                power,
+                roughness,
+            } => get_noise(
+                config,
+                Turbulence::new(Perlin::new().set_seed(seed))
+                    .set_seed(seed.wrapping_add(1))
+                    .set_frequency(*turbulence_frequency)
+                    .set_power(*power)
+                    .set_roughness(*roughness),
+                frequency,
+                skew,
+                stretch,
+            ),
+        }
+    }
+}
+
+struct GridSource<'a>(&'a DataGrid);
+
+impl NoiseFn<[f64; 2]> for GridSource<'_> {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        self.0
+            .get(point[0] as usize, point[1] as usize)
+            .unwrap_or(0.0)
+    }
+}
+
+impl LayerGraph {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).expect("Could not read layer graph file");
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Self::from_ron_str(&source),
+            _ => Self::from_toml_str(&source),
+        }
+    }
+    pub fn from_toml_str(source: &str) -> Self {
+        toml::from_str(source).expect("Could not parse layer graph TOML")
+    }
+    pub fn from_ron_str(source: &str) -> Self {
+        ron::from_str(source).expect("Could not parse layer graph RON")
+    }
+    pub fn evaluate(&self, config: &WorldConfig) -> DataGrid {
+        let mut evaluated = HashMap::new();
+        let mut visiting = HashSet::new();
+        self.evaluate_node(&self.output, config, &mut evaluated, &mut visiting)
+    }
+    fn evaluate_node(
+        &self,
+        name: &str,
+        config: &WorldConfig,
+        evaluated: &mut HashMap<String, DataGrid>,
+        visiting: &mut HashSet<String>,
+    ) -> DataGrid {
+        if let Some(data_grid) = evaluated.get(name) {
+            return data_grid.clone();
+        }
+        if !visiting.insert(name.to_string()) {
+            panic!("Layer graph contains a cycle at node '{}'", name);
+        }
+        let node = self
+            .nodes
+            .get(name)
+            .unwrap_or_else(|| panic!("Layer graph has no node named '{}'", name));
+        println!("Evaluating layer node '{}'", name);
+        let mut input = |name: &str| self.evaluate_node(name, config, evaluated, visiting);
+        let data_grid = match node {
+            LayerNode::Noise {
+                source,
+                frequency,
+                skew,
+                stretch,
+                seed_offset,
+            } => source.sample(
+                config,
+                config.noise_seed("layers").wrapping_add(*seed_offset),
+                *frequency,
+                *skew,
+                *stretch,
+            ),
+            LayerNode::Constant { value } => DataGrid::filled(config.width, config.height, *value),
+            LayerNode::AsciiGrid { path, fill } => {
+                let (data_grid, reference) = DataGrid::load_ascii_grid(path);
+                let data_grid = match reference.no_data {
+                    Some(no_data) => data_grid.fill_no_data(no_data, *fill),
+                    None => data_grid,
+                };
+                data_grid.resample(config.width, config.height)
+            }
+            LayerNode::Remap {
+                input: name,
+                min,
+                max,
+            } => input(name).remap(*min, *max),
+            LayerNode::Add { inputs } => inputs
+                .iter()
+                .map(|name| input(name))
+                .reduce(|a, b| a + b)
+                .expect("Add node requires at least one input"),
+            LayerNode::Multiply { inputs } => inputs
+                .iter()
+                .map(|name| input(name))
+                .reduce(|a, b| a * b)
+                .expect("Multiply node requires at least one input"),
+            LayerNode::Mask {
+                input: name,
+                mask,
+                background,
+            } => input(name).zip_with(&input(mask), |value, mask| {
+                background + (value - background) * mask.clamp(0.0, 1.0)
+            }),
+            LayerNode::Smoothstep {
+                input: name,
+                edge_0,
+                edge_1,
+            } => input(name).map(|value| smoothstep(*edge_0, *edge_1, value)),
+            LayerNode::Blend {
+                bottom,
+                top,
+                mode,
+                opacity,
+                mask,
+            } => {
+                let mut blended = input(bottom);
+                let top = input(top);
+                let mask = mask.as_ref().map(|mask| input(mask));
+                blended.blend(&top, [0.0, 0.0], *mode, *opacity, mask.as_ref());
+                blended
+            }
+            LayerNode::Terrace {
+                input: name,
+                control_points,
+                invert,
+            } => {
+                let values = input(name);
+                let source = GridSource(&values);
+                let terrace = control_points
+                    .iter()
+                    .fold(Terrace::new(&source), |terrace, control_point| {
+                        terrace.add_control_point(*control_point)
+                    })
+                    .invert_terraces(*invert);
+                values.map_coords(|x, y, _| terrace.get([x as f64, y as f64]))
+            }
+            LayerNode::Select {
+                low,
+                high,
+                control,
+                bounds,
+                falloff,
+            } => {
+                let low = input(low);
+                let high = input(high);
+                let control = input(control);
+                let (low_source, high_source, control_source) =
+                    (GridSource(&low), GridSource(&high), GridSource(&control));
+                let select = Select::new(&low_source, &high_source, &control_source)
+                    .set_bounds(bounds[0], bounds[1])
+                    .set_falloff(*falloff);
+                low.map_coords(|x, y, _| select.get([x as f64, y as f64]))
+            }
+        };
+        visiting.remove(name);
+        evaluated.insert(name.to_string(), data_grid.clone());
+        data_grid
+    }
+}
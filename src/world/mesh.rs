@@ -0,0 +1,369 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use super::{DataGrid, EdgeMode, World};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeshSettings {
+    /// World units between neighbouring cells.
+    pub horizontal_scale: f64,
+    /// World units for a height of one.
+    pub vertical_scale: f64,
+    /// When set, the grid is split as a quadtree and flat enough blocks are kept whole. This is
+    /// the largest height difference, before scaling, allowed between a block and the cells it
+    /// covers.
+    pub max_error: Option<f64>,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            horizontal_scale: 1.0,
+            vertical_scale: 100.0,
+            max_error: None,
+        }
+    }
+}
+
+/// Triangle mesh with y up, x along the grid columns and z along the grid rows. Triangles wind
+/// anticlockwise seen from above.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TerrainMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+struct Block {
+    x: usize,
+    y: usize,
+    size: usize,
+}
+
+fn block_error(heights: &DataGrid, block: &Block) -> f64 {
+    let corner = |x: usize, y: usize| heights.values[heights.index_of(x, y)];
+    let (x_1, y_1) = (block.x + block.size, block.y + block.size);
+    let corners = [
+        corner(block.x, block.y),
+        corner(x_1, block.y),
+        corner(block.x, y_1),
+        corner(x_1, y_1),
+    ];
+    let mut error: f64 = 0.0;
+    for y in block.y..=y_1 {
+        for x in block.x..=x_1 {
+            let fx = (x - block.x) as f64 / block.size as f64;
+            let fy = (y - block.y) as f64 / block.size as f64;
+            let top = corners[0] + (corners[1] - corners[0]) * fx;
+            let bottom = corners[2] + (corners[3] - corners[2]) * fx;
+            let estimate = top + (bottom - top) * fy;
+            error = error.max((corner(x, y) - estimate).abs());
+        }
+    }
+    error
+}
+
+/// Splits the grid into square blocks whose corners are cells. Blocks that stick out of the grid
+/// are always split, and blocks inside it are split until they fit within `max_error`.
+fn quadtree_blocks(heights: &DataGrid, max_error: f64) -> Vec<Block> {
+    let (last_x, last_y) = (heights.width - 1, heights.height - 1);
+    let mut size = 1;
+    while size < last_x.max(last_y) {
+        size *= 2;
+    }
+    let mut blocks = Vec::new();
+    let mut stack = vec![Block { x: 0, y: 0, size }];
+    while let Some(block) = stack.pop() {
+        if block.x >= last_x || block.y >= last_y {
+            continue;
+        }
+        let inside = block.x + block.size <= last_x && block.y + block.size <= last_y;
+        if block.size == 1 || (inside && block_error(heights, &block) <= max_error) {
+            blocks.push(block);
+            continue;
+        }
+        let half = block.size / 2;
+        for (offset_x, offset_y) in [(0, 0), (half, 0), (0, half), (half, half)] {
+            stack.push(Block {
+                x: block.x + offset_x,
+                y: block.y + offset_y,
+                size: half,
+            });
+        }
+    }
+    blocks
+}
+
+impl TerrainMesh {
+    pub fn from_heights(heights: &DataGrid, settings: &MeshSettings) -> Self {
+        println!("Building terrain mesh");
+        let mut mesh = TerrainMesh::default();
+        if heights.width < 2 || heights.height < 2 {
+            return mesh;
+        }
+        let blocks = match settings.max_error {
+            Some(max_error) => quadtree_blocks(heights, max_error),
+            None => heights
+                .coords()
+                .filter(|(x, y)| *x < heights.width - 1 && *y < heights.height - 1)
+                .map(|(x, y)| Block { x, y, size: 1 })
+                .collect(),
+        };
+
+        let mut corners = DataGrid::<bool>::new(heights.width, heights.height);
+        for block in &blocks {
+            for (x, y) in [
+                (block.x, block.y),
+                (block.x + block.size, block.y),
+                (block.x, block.y + block.size),
+                (block.x + block.size, block.y + block.size),
+            ] {
+                corners.set(x, y, true);
+            }
+        }
+        let (dx, dy) = heights.sobel(EdgeMode::Clamp);
+        let slope_scale = settings.vertical_scale / settings.horizontal_scale;
+        let mut vertex_ids = DataGrid::<u32>::filled(heights.width, heights.height, u32::MAX);
+        let mut vertex = |mesh: &mut TerrainMesh, x: usize, y: usize| {
+            let index = heights.index_of(x, y);
+            if vertex_ids.values[index] == u32::MAX {
+                vertex_ids.values[index] = mesh.positions.len() as u32;
+                mesh.positions.push([
+                    (x as f64 * settings.horizontal_scale) as f32,
+                    (heights.values[index] * settings.vertical_scale) as f32,
+                    (y as f64 * settings.horizontal_scale) as f32,
+                ]);
+                let normal = [
+                    -dx.values[index] * slope_scale,
+                    1.0,
+                    -dy.values[index] * slope_scale,
+                ];
+                let length = f64::sqrt(normal.iter().map(|n| n * n).sum());
+                mesh.normals.push(normal.map(|n| (n / length) as f32));
+                mesh.uvs.push([
+                    (x as f64 / (heights.width - 1) as f64) as f32,
+                    (y as f64 / (heights.height - 1) as f64) as f32,
+                ]);
+            }
+            vertex_ids.values[index]
+        };
+
+        for block in &blocks {
+            let (x_0, y_0) = (block.x, block.y);
+            let (x_1, y_1) = (x_0 + block.size, y_0 + block.size);
+            if block.size == 1 {
+                let a = vertex(&mut mesh, x_0, y_0);
+                let b = vertex(&mut mesh, x_1, y_0);
+                let c = vertex(&mut mesh, x_1, y_1);
+                let d = vertex(&mut mesh, x_0, y_1);
+                mesh.indices.extend_from_slice(&[a, c, b, a, d, c]);
+                continue;
+            }
+            // Fan around the block through every corner of a smaller neighbour on its edges,
+            // so no edge has a vertex on only one side of it.
+            let mut ring = Vec::new();
+            ring.extend((x_0..x_1).map(|x| (x, y_0)));
+            ring.extend((y_0..y_1).map(|y| (x_1, y)));
+            ring.extend((x_0 + 1..=x_1).rev().map(|x| (x, y_1)));
+            ring.extend((y_0 + 1..=y_1).rev().map(|y| (x_0, y)));
+            let ring: Vec<u32> = ring
+                .into_iter()
+                .filter(|(x, y)| corners.get(*x, *y) == Some(true))
+                .map(|(x, y)| vertex(&mut mesh, x, y))
+                .collect();
+            let centre = vertex(&mut mesh, x_0 + block.size / 2, y_0 + block.size / 2);
+            for i in 0..ring.len() {
+                let next = ring[(i + 1) % ring.len()];
+                mesh.indices.extend_from_slice(&[centre, next, ring[i]]);
+            }
+        }
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn write_obj<P: AsRef<Path>>(&self, path: P) {
+        let file = File::create(path).expect("Could not create OBJ file");
+        let mut writer = BufWriter::new(file);
+        let mut write = || -> std::io::Result<()> {
+            for [x, y, z] in &self.positions {
+                writeln!(writer, "v {} {} {}", x, y, z)?;
+            }
+            for [u, v] in &self.uvs {
+                writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+            }
+            for [x, y, z] in &self.normals {
+                writeln!(writer, "vn {} {} {}", x, y, z)?;
+            }
+            for triangle in self.indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + 1);
+                writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+            }
+            writer.flush()
+        };
+        write().expect("Could not write OBJ file");
+    }
+
+    /// Binary glTF 2.0 with a single mesh. A texture, if given, is embedded as PNG and used as
+    /// the base colour of a rough, non-metallic material.
+    pub fn to_glb(&self, texture: Option<&RgbImage>) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut views = Vec::new();
+        let mut push_view = |buffer: &mut Vec<u8>, bytes: &[u8], target: Option<u32>| {
+            while !buffer.len().is_multiple_of(4) {
+                buffer.push(0);
+            }
+            let target = target.map_or(String::new(), |target| format!(r#","target":{}"#, target));
+            views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{}{}}}"#,
+                buffer.len(),
+                bytes.len(),
+                target
+            ));
+            buffer.extend_from_slice(bytes);
+            views.len() - 1
+        };
+        let floats = |values: &[f32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+        let positions = push_view(
+            &mut buffer,
+            &floats(self.positions.concat().as_slice()),
+            Some(34962),
+        );
+        let normals = push_view(
+            &mut buffer,
+            &floats(self.normals.concat().as_slice()),
+            Some(34962),
+        );
+        let uvs = push_view(
+            &mut buffer,
+            &floats(self.uvs.concat().as_slice()),
+            Some(34962),
+        );
+        let indices: Vec<u8> = self
+            .indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect();
+        let indices = push_view(&mut buffer, &indices, Some(34963));
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for position in &self.positions {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        let accessors = [
+            format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                positions,
+                self.positions.len(),
+                min[0],
+                min[1],
+                min[2],
+                max[0],
+                max[1],
+                max[2]
+            ),
+            format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+                normals,
+                self.normals.len()
+            ),
+            format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC2"}}"#,
+                uvs,
+                self.uvs.len()
+            ),
+            format!(
+                r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+                indices,
+                self.indices.len()
+            ),
+        ];
+
+        let mut primitive =
+            String::from(r#""attributes":{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2},"indices":3"#);
+        let mut material = String::new();
+        if let Some(texture) = texture {
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png)
+                .write_image(
+                    texture.as_raw(),
+                    texture.width(),
+                    texture.height(),
+                    ColorType::Rgb8,
+                )
+                .expect("Could not encode mesh texture");
+            let image = push_view(&mut buffer, &png, None);
+            primitive += r#","material":0"#;
+            material = format!(
+                concat!(
+                    r#","materials":[{{"pbrMetallicRoughness":{{"baseColorTexture":{{"index":0}},"#,
+                    r#""metallicFactor":0,"roughnessFactor":1}}}}],"#,
+                    r#""textures":[{{"sampler":0,"source":0}}],"#,
+                    r#""samplers":[{{"magFilter":9729,"minFilter":9987}}],"#,
+                    r#""images":[{{"bufferView":{},"mimeType":"image/png"}}]"#
+                ),
+                image
+            );
+        }
+        while !buffer.len().is_multiple_of(4) {
+            buffer.push(0);
+        }
+
+        let mut json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"world"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#,
+                r#""meshes":[{{"primitives":[{{{}}}]}}],"#,
+                r#""accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]{}}}"#
+            ),
+            primitive,
+            accessors.join(","),
+            views.join(","),
+            buffer.len(),
+            material
+        );
+        while !json.len().is_multiple_of(4) {
+            json.push(' ');
+        }
+
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(json.as_bytes());
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+        glb
+    }
+
+    pub fn write_glb<P: AsRef<Path>>(&self, path: P, texture: Option<&RgbImage>) {
+        fs::write(path, self.to_glb(texture)).expect("Could not write glTF file");
+    }
+}
+
+impl World {
+    pub fn to_mesh(&self, settings: &MeshSettings) -> TerrainMesh {
+        TerrainMesh::from_heights(&self.heights, settings)
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{rivers::river_trunks, World};
+
+const START: char = '^';
+const END: char = '$';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Culture {
+    Nordic,
+    Latin,
+    Desert,
+    Elvish,
+}
+
+impl Culture {
+    fn word_list(&self) -> &'static str {
+        match self {
+            Culture::Nordic => include_str!("../../resources/names/nordic.txt"),
+            Culture::Latin => include_str!("../../resources/names/latin.txt"),
+            Culture::Desert => include_str!("../../resources/names/desert.txt"),
+            Culture::Elvish => include_str!("../../resources/names/elvish.txt"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NameSettings {
+    /// Cultures handed out to kingdoms in turn. Places outside every kingdom use the first.
+    pub cultures: Vec<Culture>,
+    /// Number of letters the Markov chain looks back on.
+    pub order: usize,
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Height above sea level a region peak must reach to be named as a mountain.
+    pub mountain_height: f64,
+}
+
+impl Default for NameSettings {
+    fn default() -> Self {
+        Self {
+            cultures: vec![
+                Culture::Nordic,
+                Culture::Latin,
+                Culture::Desert,
+                Culture::Elvish,
+            ],
+            order: 2,
+            min_length: 4,
+            max_length: 10,
+            mountain_height: 0.2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mountain {
+    pub name: String,
+    /// Region cell whose highest point this is.
+    pub cell: usize,
+    pub position: [f64; 2],
+    pub height: f64,
+}
+
+/// Names for the world's features, each list in the same order as the features it names.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaceNames {
+    pub kingdoms: Vec<String>,
+    pub settlements: Vec<String>,
+    pub rivers: Vec<String>,
+    pub mountains: Vec<Mountain>,
+}
+
+/// Letter-level Markov chain trained on a word list.
+pub struct NameGenerator {
+    order: usize,
+    transitions: HashMap<Vec<char>, Vec<char>>,
+    words: HashSet<String>,
+}
+
+impl NameGenerator {
+    pub fn new(culture: Culture, order: usize) -> Self {
+        Self::from_words(culture.word_list().lines(), order)
+    }
+
+    pub fn from_words<'a>(words: impl Iterator<Item = &'a str>, order: usize) -> Self {
+        let order = usize::max(order, 1);
+        let mut transitions: HashMap<Vec<char>, Vec<char>> = HashMap::new();
+        let mut known = HashSet::new();
+        for word in words {
+            let word = word.trim().to_lowercase();
+            if word.is_empty() {
+                continue;
+            }
+            known.insert(capitalise(&word));
+            let mut letters = vec![START; order];
+            letters.extend(word.chars());
+            letters.push(END);
+            for window in letters.windows(order + 1) {
+                transitions
+                    .entry(window[..order].to_vec())
+                    .or_default()
+                    .push(window[order]);
+            }
+        }
+        Self {
+            order,
+            transitions,
+            words: known,
+        }
+    }
+
+    /// Walks the chain to the end of a word, or gives up once the word is longer than
+    /// `max_length`.
+    fn generate_once<R: Rng>(&self, random: &mut R, max_length: usize) -> Option<String> {
+        let mut context = vec![START; self.order];
+        let mut name = String::new();
+        while name.chars().count() <= max_length {
+            let next = match self.transitions.get(&context) {
+                Some(next) => next[random.gen_range(0..next.len())],
+                None => return None,
+            };
+            if next == END {
+                return Some(capitalise(&name));
+            }
+            name.push(next);
+            context.remove(0);
+            context.push(next);
+        }
+        None
+    }
+
+    /// Draws names until one has an allowed length and isn't a training word, or gives up after
+    /// a fixed number of tries.
+    pub fn generate<R: Rng>(
+        &self,
+        random: &mut R,
+        min_length: usize,
+        max_length: usize,
+    ) -> Option<String> {
+        (0..100)
+            .filter_map(|_| self.generate_once(random, max_length))
+            .find(|name| name.chars().count() >= min_length && !self.words.contains(name))
+    }
+}
+
+fn roman_numeral(mut number: usize) -> String {
+    let numerals = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut text = String::new();
+    for (value, numeral) in numerals {
+        while number >= value {
+            text += numeral;
+            number -= value;
+        }
+    }
+    text
+}
+
+fn capitalise(name: &str) -> String {
+    let mut letters = name.chars();
+    match letters.next() {
+        Some(first) => first.to_uppercase().chain(letters).collect(),
+        None => String::new(),
+    }
+}
+
+/// The highest point of every region whose peak stands above all of its neighbours and at
+/// least `mountain_height` above the sea.
+pub fn find_mountains(world: &World, mountain_height: f64) -> Vec<Mountain> {
+    let regions = &world.regions;
+    let mut highest = vec![(f64::NEG_INFINITY, 0); regions.cells.len()];
+    for (index, id) in regions.cell_ids.values.iter().enumerate() {
+        let height = world.heights.values[index];
+        if let Some(highest) = highest.get_mut(*id as usize) {
+            if height > highest.0 {
+                *highest = (height, index);
+            }
+        }
+    }
+    regions
+        .cells
+        .iter()
+        .filter(|cell| cell.max_height - world.sea_level >= mountain_height)
+        .filter(|cell| {
+            cell.neighbours
+                .iter()
+                .all(|neighbour| regions.cells[*neighbour].max_height < cell.max_height)
+        })
+        .map(|cell| {
+            let (height, index) = highest[cell.id];
+            let (x, y) = world.heights.coords_of(index);
+            Mountain {
+                name: String::new(),
+                cell: cell.id,
+                position: [x as f64, y as f64],
+                height,
+            }
+        })
+        .collect()
+}
+
+/// Names kingdoms, settlements, rivers and mountains in the culture of the kingdom they lie in.
+/// Every name comes from its own random stream, so it stays the same as long as the feature
+/// does. Rivers are named once per trunk, so every segment along it shares the name. A name
+/// drawn again for a later feature gets a numeral, such as "Ostby II", and a feature whose draws
+/// all fail is called by its kind and number.
+pub fn name_places(world: &World, settings: &NameSettings) -> PlaceNames {
+    println!("Naming places");
+    let cultures = if settings.cultures.is_empty() {
+        vec![Culture::Nordic]
+    } else {
+        settings.cultures.clone()
+    };
+    let generators: HashMap<Culture, NameGenerator> = cultures
+        .iter()
+        .map(|culture| (*culture, NameGenerator::new(*culture, settings.order)))
+        .collect();
+    let kingdom_culture = |kingdom: Option<usize>| match kingdom {
+        Some(kingdom) => cultures[kingdom % cultures.len()],
+        None => cultures[0],
+    };
+    let culture_at = |point: [f64; 2]| {
+        let kingdom = world
+            .regions
+            .cell_at(point[0] as usize, point[1] as usize)
+            .and_then(|cell| world.region_kingdoms.get(cell.id).copied().flatten());
+        kingdom_culture(kingdom)
+    };
+
+    let mut used: HashMap<String, usize> = HashMap::new();
+    let mut name = |culture: Culture, kind: &str, number: usize| {
+        let mut random = world.config.rng(&format!("names/{}s/{}", kind, number));
+        let name = generators[&culture]
+            .generate(&mut random, settings.min_length, settings.max_length)
+            .unwrap_or_else(|| format!("{} {}", capitalise(kind), number));
+        let count = used.entry(name.clone()).or_default();
+        *count += 1;
+        match *count {
+            1 => name,
+            count => format!("{} {}", name, roman_numeral(count)),
+        }
+    };
+
+    let kingdoms = world
+        .kingdoms
+        .iter()
+        .map(|kingdom| name(kingdom_culture(Some(kingdom.id)), "kingdom", kingdom.id))
+        .collect();
+    let settlements = world
+        .settlements
+        .iter()
+        .enumerate()
+        .map(|(i, settlement)| name(culture_at(settlement.position), "settlement", i))
+        .collect();
+    let trunks = river_trunks(&world.rivers);
+    let mut trunk_names = HashMap::new();
+    let rivers = trunks
+        .iter()
+        .map(|trunk| {
+            trunk_names
+                .entry(*trunk)
+                .or_insert_with(|| {
+                    let points = world.rivers[*trunk].land_points();
+                    let mouth = points[points.len() - 1];
+                    name(culture_at(mouth), "river", *trunk)
+                })
+                .clone()
+        })
+        .collect();
+    let mut mountains = find_mountains(world, settings.mountain_height);
+    for mountain in &mut mountains {
+        mountain.name = name(culture_at(mountain.position), "mountain", mountain.cell);
+    }
+
+    PlaceNames {
+        kingdoms,
+        settlements,
+        rivers,
+        mountains,
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use fast_poisson::Poisson2D;
+use serde::{Deserialize, Serialize};
+use spade::{DelaunayTriangulation, HasPosition, Point2, Triangulation};
+
+use super::{coast, DataGrid, WorldConfig};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegionSettings {
+    /// Minimum distance between two cell sites.
+    pub radius: f64,
+}
+
+impl Default for RegionSettings {
+    fn default() -> Self {
+        Self { radius: 35.0 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RegionCell {
+    pub id: usize,
+    pub site: [f64; 2],
+    pub centroid: [f64; 2],
+    pub polygon: Vec<[f64; 2]>,
+    pub neighbours: Vec<usize>,
+    pub area: usize,
+    pub mean_height: f64,
+    pub min_height: f64,
+    pub max_height: f64,
+    pub land_fraction: f64,
+    pub biome: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionGraph {
+    pub cells: Vec<RegionCell>,
+    pub cell_ids: DataGrid<u32>,
+}
+
+struct Site {
+    position: Point2<f64>,
+    id: usize,
+}
+
+impl HasPosition for Site {
+    type Scalar = f64;
+    fn position(&self) -> Point2<f64> {
+        self.position
+    }
+}
+
+fn distance_2(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1])
+}
+
+/// Clips a convex polygon to the half of the plane closer to `site` than to `other`. Every
+/// polygon vertex carries the neighbour whose bisector forms the edge leaving it, or `None` for
+/// an edge on the map bounds.
+fn clip_polygon(
+    polygon: &[([f64; 2], Option<usize>)],
+    site: [f64; 2],
+    other: [f64; 2],
+    other_id: usize,
+) -> Vec<([f64; 2], Option<usize>)> {
+    let normal = [other[0] - site[0], other[1] - site[1]];
+    let middle = [(site[0] + other[0]) / 2.0, (site[1] + other[1]) / 2.0];
+    let side =
+        |point: [f64; 2]| (point[0] - middle[0]) * normal[0] + (point[1] - middle[1]) * normal[1];
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, (start, tag)) in polygon.iter().enumerate() {
+        let (end, _) = polygon[(i + 1) % polygon.len()];
+        let start_side = side(*start);
+        let end_side = side(end);
+        let crossing = || {
+            let t = start_side / (start_side - end_side);
+            [
+                start[0] + (end[0] - start[0]) * t,
+                start[1] + (end[1] - start[1]) * t,
+            ]
+        };
+        match (start_side <= 0.0, end_side <= 0.0) {
+            (true, true) => clipped.push((*start, *tag)),
+            (true, false) => {
+                clipped.push((*start, *tag));
+                clipped.push((crossing(), Some(other_id)));
+            }
+            (false, true) => clipped.push((crossing(), *tag)),
+            (false, false) => {}
+        }
+    }
+    clipped
+}
+
+fn polygon_centroid(polygon: &[[f64; 2]]) -> Option<[f64; 2]> {
+    let mut area = 0.0;
+    let mut centroid = [0.0, 0.0];
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let cross = a[0] * b[1] - b[0] * a[1];
+        area += cross;
+        centroid[0] += (a[0] + b[0]) * cross;
+        centroid[1] += (a[1] + b[1]) * cross;
+    }
+    if area.abs() <= f64::EPSILON {
+        return None;
+    }
+    Some([centroid[0] / (3.0 * area), centroid[1] / (3.0 * area)])
+}
+
+impl RegionGraph {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: Vec::new(),
+            cell_ids: DataGrid::new(width, height),
+        }
+    }
+
+    pub fn cell_at(&self, x: usize, y: usize) -> Option<&RegionCell> {
+        self.cell_ids
+            .get(x, y)
+            .and_then(|id| self.cells.get(id as usize))
+    }
+
+    /// The polygon edge between two neighbouring cells, found as the vertices of the first cell
+    /// that are as close to the second site as to its own.
+    pub fn shared_edge(&self, a: usize, b: usize) -> Option<[[f64; 2]; 2]> {
+        let (a, b) = (&self.cells[a], &self.cells[b]);
+        let tolerance = 1e-6 * distance_2(a.site, b.site);
+        let on_bisector: Vec<[f64; 2]> = a
+            .polygon
+            .iter()
+            .copied()
+            .filter(|point| {
+                (distance_2(*point, a.site) - distance_2(*point, b.site)).abs() <= tolerance
+            })
+            .collect();
+        match on_bisector[..] {
+            [start, end] => Some([start, end]),
+            _ => None,
+        }
+    }
+}
+
+impl RegionCell {
+    pub fn is_land(&self) -> bool {
+        self.land_fraction >= 0.5
+    }
+}
+
+/// Scatters Poisson-disk sites over the world and builds their Voronoi cells, clipped to the
+/// map bounds, with per-cell statistics sampled from the world layers.
+pub fn build_regions(
+    config: &WorldConfig,
+    sea_level: f64,
+    heights: &DataGrid,
+    biomes: &DataGrid<u8>,
+    settings: &RegionSettings,
+) -> RegionGraph {
+    println!("Building region graph");
+    let (width, height) = (heights.width, heights.height);
+    let sites: Vec<[f64; 2]> = Poisson2D::new()
+        .with_dimensions([width as f64, height as f64], settings.radius)
+        .with_seed(config.stream_seed("regions"))
+        .iter()
+        .collect();
+    if sites.is_empty() {
+        return RegionGraph::new(width, height);
+    }
+
+    let mut triangulation: DelaunayTriangulation<Site> = DelaunayTriangulation::new();
+    for (id, site) in sites.iter().enumerate() {
+        triangulation
+            .insert(Site {
+                position: Point2::new(site[0], site[1]),
+                id,
+            })
+            .expect("Could not insert region site");
+    }
+    let mut delaunay_neighbours = vec![Vec::new(); sites.len()];
+    for vertex in triangulation.vertices() {
+        delaunay_neighbours[vertex.data().id] =
+            vertex.out_edges().map(|edge| edge.to().data().id).collect();
+    }
+
+    let bounds = [
+        ([0.0, 0.0], None),
+        ([width as f64, 0.0], None),
+        ([width as f64, height as f64], None),
+        ([0.0, height as f64], None),
+    ];
+    let mut cells: Vec<RegionCell> = sites
+        .iter()
+        .enumerate()
+        .map(|(id, site)| {
+            let mut polygon = bounds.to_vec();
+            for neighbour in &delaunay_neighbours[id] {
+                polygon = clip_polygon(&polygon, *site, sites[*neighbour], *neighbour);
+            }
+            let mut neighbours: Vec<usize> = polygon.iter().filter_map(|(_, tag)| *tag).collect();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            let polygon: Vec<[f64; 2]> = polygon.into_iter().map(|(point, _)| point).collect();
+            RegionCell {
+                id,
+                site: *site,
+                centroid: polygon_centroid(&polygon).unwrap_or(*site),
+                polygon,
+                neighbours,
+                area: 0,
+                mean_height: 0.0,
+                min_height: f64::INFINITY,
+                max_height: f64::NEG_INFINITY,
+                land_fraction: 0.0,
+                biome: 0,
+            }
+        })
+        .collect();
+
+    let mut cell_ids = DataGrid::<u32>::new(width, height);
+    let mut biome_counts: Vec<HashMap<u8, usize>> = vec![HashMap::new(); cells.len()];
+    let land = coast::land_mask(heights, sea_level);
+    let mut current = 0;
+    for (x, y) in heights.coords() {
+        let point = [x as f64 + 0.5, y as f64 + 0.5];
+        while let Some(closer) = delaunay_neighbours[current]
+            .iter()
+            .copied()
+            .find(|neighbour| {
+                distance_2(sites[*neighbour], point) < distance_2(sites[current], point)
+            })
+        {
+            current = closer;
+        }
+        let index = heights.index_of(x, y);
+        let height = heights.values[index];
+        let cell = &mut cells[current];
+        cell_ids.values[index] = current as u32;
+        cell.area += 1;
+        cell.mean_height += height;
+        cell.min_height = f64::min(cell.min_height, height);
+        cell.max_height = f64::max(cell.max_height, height);
+        cell.land_fraction += land.values[index] as u8 as f64;
+        *biome_counts[current]
+            .entry(biomes.values[index])
+            .or_default() += 1;
+    }
+    for (cell, counts) in cells.iter_mut().zip(biome_counts) {
+        if cell.area > 0 {
+            cell.mean_height /= cell.area as f64;
+            cell.land_fraction /= cell.area as f64;
+        }
+        cell.biome = counts
+            .into_iter()
+            .max_by_key(|(biome, count)| (*count, *biome))
+            .map_or(0, |(biome, _)| biome);
+    }
+
+    RegionGraph { cells, cell_ids }
+}
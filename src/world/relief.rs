@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use super::{lakes::NO_LAKE, ColorRamp, ColorStop, DataGrid, EdgeMode, RampMode, World};
+
+/// A light for hillshading. Azimuth is in compass degrees, so 0 shines from the top of the map
+/// and 90 from the right. Altitude is in degrees above the horizon.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub azimuth: f64,
+    pub altitude: f64,
+    pub weight: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReliefSettings {
+    pub lights: Vec<Light>,
+    /// Vertical exaggeration applied to heights, which span `[0, 1]` over the whole map, before
+    /// shading.
+    pub z_factor: f64,
+    /// How far the shading brightens and darkens land. Flat ground keeps its tint.
+    pub shade_strength: f64,
+    /// Shading strength under water, usually weaker so the sea floor reads as depth.
+    pub water_shade_strength: f64,
+    /// Tint by height above sea level.
+    pub land_ramp: ColorRamp,
+    /// Tint by depth below sea level or below the surface of a lake.
+    pub water_ramp: ColorRamp,
+    /// How much biome colours replace the height tint on land, when a biome layer is drawn.
+    pub biome_opacity: f64,
+}
+
+impl Default for ReliefSettings {
+    fn default() -> Self {
+        let light = |azimuth, weight| Light {
+            azimuth,
+            altitude: 45.0,
+            weight,
+        };
+        let stop = |value, color| ColorStop { value, color };
+        Self {
+            lights: vec![
+                light(225.0, 0.25),
+                light(270.0, 0.5),
+                light(315.0, 1.0),
+                light(360.0, 0.5),
+            ],
+            z_factor: 200.0,
+            shade_strength: 0.6,
+            water_shade_strength: 0.2,
+            land_ramp: ColorRamp::new(
+                vec![
+                    stop(0.0, [96, 152, 88]),
+                    stop(0.05, [148, 184, 104]),
+                    stop(0.15, [212, 196, 136]),
+                    stop(0.3, [164, 132, 100]),
+                    stop(0.45, [244, 244, 244]),
+                ],
+                RampMode::Smooth,
+            ),
+            water_ramp: ColorRamp::new(
+                vec![
+                    stop(0.0, [128, 188, 224]),
+                    stop(0.1, [48, 100, 168]),
+                    stop(0.3, [16, 44, 104]),
+                ],
+                RampMode::Smooth,
+            ),
+            biome_opacity: 0.7,
+        }
+    }
+}
+
+/// Weighted multi-directional hillshade, where 1 is a surface facing the light and 0 is one
+/// facing away from or parallel to it.
+pub fn hillshade(heights: &DataGrid, lights: &[Light], z_factor: f64) -> DataGrid {
+    let (dx, dy) = heights.sobel(EdgeMode::Clamp);
+    let total_weight: f64 = lights.iter().map(|light| light.weight).sum();
+    let directions: Vec<[f64; 3]> = lights
+        .iter()
+        .map(|light| {
+            let (azimuth, altitude) = (light.azimuth.to_radians(), light.altitude.to_radians());
+            [
+                azimuth.sin() * altitude.cos(),
+                -azimuth.cos() * altitude.cos(),
+                altitude.sin(),
+            ]
+        })
+        .collect();
+    dx.zip_with(&dy, |dx, dy| {
+        let normal = [-dx * z_factor, -dy * z_factor, 1.0];
+        let length = f64::sqrt(normal[0] * normal[0] + normal[1] * normal[1] + 1.0);
+        let shade: f64 = lights
+            .iter()
+            .zip(&directions)
+            .map(|(light, direction)| {
+                let dot = normal[0] * direction[0] + normal[1] * direction[1] + direction[2];
+                light.weight * (dot / length).max(0.0)
+            })
+            .sum();
+        if total_weight > 0.0 {
+            shade / total_weight
+        } else {
+            1.0
+        }
+    })
+}
+
+/// Renders a shaded relief map. Land is tinted by height, or by biome when `biomes` holds the
+/// biome grid and its palette, and water by depth, with the hillshade laid over both.
+pub fn render_relief(
+    heights: &DataGrid,
+    sea_level: f64,
+    lakes: Option<(&DataGrid<u32>, &HashMap<u32, f64>)>,
+    biomes: Option<(&DataGrid<u8>, &[[u8; 3]])>,
+    settings: &ReliefSettings,
+) -> RgbImage {
+    println!("Rendering relief");
+    let shade = hillshade(heights, &settings.lights, settings.z_factor);
+    let total_weight: f64 = settings.lights.iter().map(|light| light.weight).sum();
+    let flat_shade = if total_weight > 0.0 {
+        settings
+            .lights
+            .iter()
+            .map(|light| light.weight * light.altitude.to_radians().sin())
+            .sum::<f64>()
+            / total_weight
+    } else {
+        1.0
+    };
+    RgbImage::from_fn(heights.width as u32, heights.height as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let index = heights.index_of(x, y);
+        let height = heights.values[index];
+        let lake_level = lakes.and_then(|(labels, levels)| match labels.values[index] {
+            NO_LAKE => None,
+            label => levels.get(&label).copied(),
+        });
+        let (color, strength) = match lake_level {
+            Some(level) => (
+                settings.water_ramp.color_at(level - height, x, y),
+                settings.water_shade_strength,
+            ),
+            None if height < sea_level => (
+                settings.water_ramp.color_at(sea_level - height, x, y),
+                settings.water_shade_strength,
+            ),
+            None => {
+                let tint = settings.land_ramp.color_at(height - sea_level, x, y);
+                let color = match biomes {
+                    Some((biomes, palette)) => match palette.get(biomes.values[index] as usize) {
+                        Some(biome) => [0, 1, 2].map(|i| {
+                            tint[i] + (biome[i] as f64 - tint[i]) * settings.biome_opacity
+                        }),
+                        None => tint,
+                    },
+                    None => tint,
+                };
+                (color, settings.shade_strength)
+            }
+        };
+        let factor = 1.0 + strength * (shade.values[index] - flat_shade) / flat_shade.max(0.1);
+        Rgb(color.map(|channel| (channel * factor).round().clamp(0.0, 255.0) as u8))
+    })
+}
+
+impl World {
+    /// Shaded relief of the generated world, with lakes and, if asked for and classified, biomes.
+    pub fn render_relief(&self, settings: &ReliefSettings, biomes: bool) -> RgbImage {
+        let lake_levels: HashMap<u32, f64> = self
+            .lakes
+            .iter()
+            .map(|lake| (lake.id, lake.surface_level))
+            .collect();
+        let biomes = if biomes && !self.biome_palette.is_empty() {
+            Some((&self.biomes, self.biome_palette.as_slice()))
+        } else {
+            None
+        };
+        render_relief(
+            &self.heights,
+            self.sea_level,
+            Some((&self.lake_labels, &lake_levels)),
+            biomes,
+            settings,
+        )
+    }
+}
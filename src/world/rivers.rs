@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    data_grid::Neighbourhood,
+    lakes::{fill_depressions, DRAIN_EPSILON},
+    DataGrid,
+};
+
+pub const NO_FLOW: u8 = u8::MAX;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiverSettings {
+    /// Number of upstream cells that must drain through a cell before it carries a river.
+    pub threshold: f64,
+    pub width_scale: f64,
+    pub carve_depth: Option<f64>,
+}
+
+impl Default for RiverSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 400.0,
+            width_scale: 0.05,
+            carve_depth: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct River {
+    pub points: Vec<[f64; 2]>,
+    /// Width at the last cell the river drains alone, before it joins another river or the sea.
+    pub width: f64,
+    pub order: u8,
+    /// Whether the last point is the first sea cell the river reaches.
+    #[serde(default)]
+    pub mouth: bool,
+}
+
+impl River {
+    /// The points above sea level, leaving out the mouth.
+    pub fn land_points(&self) -> &[[f64; 2]] {
+        &self.points[..self.points.len() - self.mouth as usize]
+    }
+}
+
+/// Points every cell at its steepest downhill neighbour, as an index into the eight neighbour
+/// offsets. Cells without a lower neighbour get `NO_FLOW`.
+pub fn flow_directions(heights: &DataGrid) -> DataGrid<u8> {
+    let offsets = Neighbourhood::Eight.offsets();
+    heights.map_coords(|x, y, height| {
+        let mut direction = NO_FLOW;
+        let mut steepest = 0.0;
+        for (i, (offset_x, offset_y)) in offsets.iter().enumerate() {
+            let neighbour_x = x as isize + offset_x;
+            let neighbour_y = y as isize + offset_y;
+            if neighbour_x < 0 || neighbour_y < 0 {
+                continue;
+            }
+            let neighbour_height = match heights.get(neighbour_x as usize, neighbour_y as usize) {
+                Some(neighbour_height) => neighbour_height,
+                None => continue,
+            };
+            let distance = f64::sqrt((offset_x * offset_x + offset_y * offset_y) as f64);
+            let slope = (height - neighbour_height) / distance;
+            if slope > steepest {
+                steepest = slope;
+                direction = i as u8;
+            }
+        }
+        direction
+    })
+}
+
+pub fn downstream(directions: &DataGrid<u8>, x: usize, y: usize) -> Option<(usize, usize)> {
+    let direction = directions.get(x, y)?;
+    if direction == NO_FLOW {
+        return None;
+    }
+    let (offset_x, offset_y) = Neighbourhood::Eight.offsets()[direction as usize];
+    Some((
+        (x as isize + offset_x) as usize,
+        (y as isize + offset_y) as usize,
+    ))
+}
+
+fn cells_from_highest(heights: &DataGrid) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..heights.len()).collect();
+    order.sort_by(|a, b| heights.values[*b].total_cmp(&heights.values[*a]));
+    order
+}
+
+pub fn flow_accumulation(heights: &DataGrid, directions: &DataGrid<u8>) -> DataGrid {
+    let mut accumulation = DataGrid::filled(heights.width, heights.height, 1.0);
+    for index in cells_from_highest(heights) {
+        let (x, y) = heights.coords_of(index);
+        if let Some((next_x, next_y)) = downstream(directions, x, y) {
+            let next_index = accumulation.index_of(next_x, next_y);
+            accumulation.values[next_index] += accumulation.values[index];
+        }
+    }
+    accumulation
+}
+
+/// Traces river polylines through every land cell whose accumulation meets the threshold.
+/// Polylines are split at confluences, so every river has a single Strahler order. Flow is routed
+/// over a depression-filled copy of the heights, so rivers pass through pits and lakes, and ends
+/// at the first cell below sea level or at the map edge.
+pub fn extract_rivers(heights: &DataGrid, sea_level: f64, settings: &RiverSettings) -> Vec<River> {
+    println!("Extracting rivers");
+    let sea = heights.map(|height| height < sea_level);
+    let heights = &fill_depressions(heights, sea_level, DRAIN_EPSILON);
+    let directions = flow_directions(heights);
+    let accumulation = flow_accumulation(heights, &directions);
+    let is_river = accumulation.zip_with(&sea, |value, sea| value >= settings.threshold && !sea);
+
+    let mut upstream_count = DataGrid::<u8>::new(heights.width, heights.height);
+    let mut orders = DataGrid::<u8>::new(heights.width, heights.height);
+    let mut max_upstream_order = DataGrid::<u8>::new(heights.width, heights.height);
+    let mut max_upstream_order_count = DataGrid::<u8>::new(heights.width, heights.height);
+    for index in cells_from_highest(heights) {
+        if !is_river.values[index] {
+            continue;
+        }
+        let order = match max_upstream_order.values[index] {
+            0 => 1,
+            max_order if max_upstream_order_count.values[index] > 1 => max_order + 1,
+            max_order => max_order,
+        };
+        orders.values[index] = order;
+        let (x, y) = heights.coords_of(index);
+        if let Some((next_x, next_y)) = downstream(&directions, x, y) {
+            let next_index = heights.index_of(next_x, next_y);
+            upstream_count.values[next_index] += 1;
+            if order > max_upstream_order.values[next_index] {
+                max_upstream_order.values[next_index] = order;
+                max_upstream_order_count.values[next_index] = 1;
+            } else if order == max_upstream_order.values[next_index] {
+                max_upstream_order_count.values[next_index] += 1;
+            }
+        }
+    }
+
+    let mut rivers = Vec::new();
+    for index in cells_from_highest(heights) {
+        if !is_river.values[index] || upstream_count.values[index] == 1 {
+            continue;
+        }
+        let (mut x, mut y) = heights.coords_of(index);
+        let mut points = vec![[x as f64, y as f64]];
+        let mut mouth = false;
+        while let Some((next_x, next_y)) = downstream(&directions, x, y) {
+            let next_index = heights.index_of(next_x, next_y);
+            if sea.values[next_index] {
+                points.push([next_x as f64, next_y as f64]);
+                mouth = true;
+                break;
+            }
+            if !is_river.values[next_index] {
+                break;
+            }
+            points.push([next_x as f64, next_y as f64]);
+            if upstream_count.values[next_index] > 1 {
+                break;
+            }
+            x = next_x;
+            y = next_y;
+        }
+        rivers.push(River {
+            points,
+            width: settings.width_scale * accumulation.get(x, y).unwrap().sqrt(),
+            order: orders.values[index],
+            mouth,
+        });
+    }
+    rivers
+}
+
+/// For every river, the most downstream river of the trunk it belongs to. At each confluence the
+/// trunk carries on up the river of the highest order, and of those the widest, so a named river
+/// can be followed across the segments `extract_rivers` splits it into.
+pub fn river_trunks(rivers: &[River]) -> Vec<usize> {
+    let cell = |point: [f64; 2]| (point[0] as usize, point[1] as usize);
+    let starts: HashMap<(usize, usize), usize> = rivers
+        .iter()
+        .enumerate()
+        .map(|(i, river)| (cell(river.points[0]), i))
+        .collect();
+    let downstream: Vec<Option<usize>> = rivers
+        .iter()
+        .enumerate()
+        .map(|(i, river)| {
+            if river.mouth {
+                return None;
+            }
+            let end = cell(river.points[river.points.len() - 1]);
+            starts.get(&end).copied().filter(|next| *next != i)
+        })
+        .collect();
+    let mut main_tributary: Vec<Option<usize>> = vec![None; rivers.len()];
+    for (i, next) in downstream.iter().enumerate() {
+        if let Some(next) = *next {
+            let is_main = main_tributary[next].is_none_or(|main| {
+                let (river, main) = (&rivers[i], &rivers[main]);
+                (river.order, river.width) > (main.order, main.width)
+            });
+            if is_main {
+                main_tributary[next] = Some(i);
+            }
+        }
+    }
+    (0..rivers.len())
+        .map(|mut i| {
+            while let Some(next) = downstream[i] {
+                if main_tributary[next] != Some(i) {
+                    break;
+                }
+                i = next;
+            }
+            i
+        })
+        .collect()
+}
+
+/// Cuts river beds into the land. Cells below sea level are left alone and no cell is cut below
+/// it.
+pub fn carve_rivers(heights: &DataGrid, sea_level: f64, rivers: &[River], depth: f64) -> DataGrid {
+    let mut carving = DataGrid::new(heights.width, heights.height);
+    for river in rivers {
+        let radius = f64::max(river.width / 2.0, 0.5);
+        let reach = radius.ceil() as isize;
+        for point in river.land_points() {
+            for offset_y in -reach..=reach {
+                for offset_x in -reach..=reach {
+                    let x = point[0] as isize + offset_x;
+                    let y = point[1] as isize + offset_y;
+                    if x < 0 || y < 0 || !carving.contains(x as usize, y as usize) {
+                        continue;
+                    }
+                    let distance = f64::sqrt((offset_x * offset_x + offset_y * offset_y) as f64);
+                    if distance > radius {
+                        continue;
+                    }
+                    let index = carving.index_of(x as usize, y as usize);
+                    let amount = depth * (1.0 - distance / (radius + 1.0));
+                    carving.values[index] = f64::max(carving.values[index], amount);
+                }
+            }
+        }
+    }
+    heights.zip_with(&carving, |height, carving| {
+        if height < sea_level {
+            height
+        } else {
+            f64::max(height - carving, sea_level)
+        }
+    })
+}
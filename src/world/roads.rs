@@ -0,0 +1,411 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use line_drawing::Bresenham;
+use serde::{Deserialize, Serialize};
+
+use super::{data_grid::Neighbourhood, DataGrid, Settlement};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoadSettings {
+    /// Extra cost per unit of height climbed or descended, relative to the distance walked.
+    pub slope_cost: f64,
+    /// Cost of bridging a river or lake cell.
+    pub water_cost: f64,
+    /// Multiplier on the cost of cells that already carry a road.
+    pub road_reuse: f64,
+    /// Number of nearest settlements each settlement considers linking to.
+    pub links_per_settlement: usize,
+    /// A link left out of the spanning tree is still built when the detour over the network
+    /// costs more than this many times the link itself.
+    pub shortcut_ratio: f64,
+    /// How far a path may stray outside the box around its two ends, as a fraction of the
+    /// distance between them. Paths that don't fit are searched for over the whole map.
+    pub search_margin: f64,
+}
+
+impl Default for RoadSettings {
+    fn default() -> Self {
+        Self {
+            slope_cost: 400.0,
+            water_cost: 30.0,
+            road_reuse: 0.3,
+            links_per_settlement: 4,
+            shortcut_ratio: 1.6,
+            search_margin: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Road {
+    /// Indices of the settlements at either end.
+    pub from: usize,
+    pub to: usize,
+    pub points: Vec<[f64; 2]>,
+    pub cost: f64,
+}
+
+struct Step {
+    priority: f64,
+    index: usize,
+}
+
+impl PartialEq for Step {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Step {}
+
+impl PartialOrd for Step {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Step {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+/// Least number of cells a path search may stray outside the box around its two ends.
+const MIN_SEARCH_MARGIN: f64 = 16.0;
+
+/// Buffers shared by every path search. A cell's cost and parent only count when it was
+/// reached in the current generation, so nothing has to be cleared between searches.
+struct Search {
+    generation: u32,
+    reached: Vec<u32>,
+    costs: Vec<f64>,
+    came_from: Vec<usize>,
+}
+
+impl Search {
+    fn new(len: usize) -> Self {
+        Self {
+            generation: 0,
+            reached: vec![0; len],
+            costs: vec![f64::INFINITY; len],
+            came_from: vec![usize::MAX; len],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            self.reached.fill(0);
+            self.generation = 1;
+        }
+    }
+
+    fn cost(&self, index: usize) -> f64 {
+        if self.reached[index] == self.generation {
+            self.costs[index]
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn set(&mut self, index: usize, cost: f64, from: usize) {
+        self.reached[index] = self.generation;
+        self.costs[index] = cost;
+        self.came_from[index] = from;
+    }
+}
+
+struct CostField<'a> {
+    sea_level: f64,
+    heights: &'a DataGrid,
+    fresh_water: &'a DataGrid<bool>,
+    roads: DataGrid<bool>,
+    settings: &'a RoadSettings,
+}
+
+impl CostField<'_> {
+    fn step_cost(&self, from: usize, to: usize, distance: f64) -> Option<f64> {
+        let height = self.heights.values[to];
+        if height < self.sea_level {
+            return None;
+        }
+        let climb = (height - self.heights.values[from]).abs();
+        let mut cost = distance + climb * self.settings.slope_cost;
+        if self.fresh_water.values[to] {
+            cost += self.settings.water_cost;
+        }
+        if self.roads.values[to] {
+            cost *= self.settings.road_reuse;
+        }
+        Some(cost)
+    }
+
+    /// A* between two cells, kept to a box around them and widened to the whole map if no path
+    /// fits. The heuristic is the straight distance over flat unpaved ground, so next to
+    /// existing roads it can overestimate, which favours direct paths slightly over reuse.
+    fn find_path(
+        &self,
+        search: &mut Search,
+        start: (usize, usize),
+        goal: (usize, usize),
+    ) -> Option<(Vec<[f64; 2]>, f64)> {
+        let grid = self.heights;
+        let dx = start.0 as f64 - goal.0 as f64;
+        let dy = start.1 as f64 - goal.1 as f64;
+        let margin = f64::max(
+            f64::sqrt(dx * dx + dy * dy) * self.settings.search_margin,
+            MIN_SEARCH_MARGIN,
+        ) as usize;
+        let bounds = (
+            (
+                usize::min(start.0, goal.0).saturating_sub(margin),
+                usize::min(start.1, goal.1).saturating_sub(margin),
+            ),
+            (
+                usize::min(usize::max(start.0, goal.0) + margin, grid.width - 1),
+                usize::min(usize::max(start.1, goal.1) + margin, grid.height - 1),
+            ),
+        );
+        let whole_map = ((0, 0), (grid.width - 1, grid.height - 1));
+        let path = self.search_within(search, start, goal, bounds);
+        if path.is_some() || bounds == whole_map {
+            return path;
+        }
+        self.search_within(search, start, goal, whole_map)
+    }
+
+    fn search_within(
+        &self,
+        search: &mut Search,
+        start: (usize, usize),
+        goal: (usize, usize),
+        ((min_x, min_y), (max_x, max_y)): ((usize, usize), (usize, usize)),
+    ) -> Option<(Vec<[f64; 2]>, f64)> {
+        let grid = self.heights;
+        let start = grid.index_of(start.0, start.1);
+        let goal = grid.index_of(goal.0, goal.1);
+        let heuristic = |index: usize| {
+            let (x, y) = grid.coords_of(index);
+            let (goal_x, goal_y) = grid.coords_of(goal);
+            let dx = x as f64 - goal_x as f64;
+            let dy = y as f64 - goal_y as f64;
+            f64::sqrt(dx * dx + dy * dy)
+        };
+        search.reset();
+        let mut open = BinaryHeap::new();
+        search.set(start, 0.0, usize::MAX);
+        open.push(Step {
+            priority: heuristic(start),
+            index: start,
+        });
+        while let Some(step) = open.pop() {
+            if step.index == goal {
+                break;
+            }
+            if step.priority > search.cost(step.index) + heuristic(step.index) {
+                continue;
+            }
+            let (x, y) = grid.coords_of(step.index);
+            for (neighbour_x, neighbour_y) in grid.neighbours(x, y, Neighbourhood::Eight) {
+                if neighbour_x < min_x
+                    || neighbour_y < min_y
+                    || neighbour_x > max_x
+                    || neighbour_y > max_y
+                {
+                    continue;
+                }
+                let neighbour = grid.index_of(neighbour_x, neighbour_y);
+                let distance = if neighbour_x != x && neighbour_y != y {
+                    std::f64::consts::SQRT_2
+                } else {
+                    1.0
+                };
+                let cost = match self.step_cost(step.index, neighbour, distance) {
+                    Some(cost) => search.cost(step.index) + cost,
+                    None => continue,
+                };
+                if cost < search.cost(neighbour) {
+                    search.set(neighbour, cost, step.index);
+                    open.push(Step {
+                        priority: cost + heuristic(neighbour),
+                        index: neighbour,
+                    });
+                }
+            }
+        }
+        if !search.cost(goal).is_finite() {
+            return None;
+        }
+
+        let mut points = Vec::new();
+        let mut index = goal;
+        loop {
+            let (x, y) = grid.coords_of(index);
+            points.push([x as f64, y as f64]);
+            if index == start {
+                break;
+            }
+            index = search.came_from[index];
+        }
+        points.reverse();
+        Some((points, search.cost(goal)))
+    }
+}
+
+fn find_root(parents: &mut [usize], node: usize) -> usize {
+    let mut root = node;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[node] = root;
+    root
+}
+
+/// Cheapest cost between two settlements over the links chosen so far.
+fn network_cost(links: &[(usize, usize, f64)], nodes: usize, from: usize, to: usize) -> f64 {
+    let mut costs = vec![f64::INFINITY; nodes];
+    let mut done = vec![false; nodes];
+    costs[from] = 0.0;
+    while let Some(node) = (0..nodes)
+        .filter(|node| !done[*node] && costs[*node].is_finite())
+        .min_by(|a, b| costs[*a].total_cmp(&costs[*b]))
+    {
+        if node == to {
+            break;
+        }
+        done[node] = true;
+        for (a, b, cost) in links {
+            let other = match node {
+                node if node == *a => *b,
+                node if node == *b => *a,
+                _ => continue,
+            };
+            costs[other] = f64::min(costs[other], costs[node] + cost);
+        }
+    }
+    costs[to]
+}
+
+fn settlement_cell(settlement: &Settlement) -> (usize, usize) {
+    (
+        settlement.position[0] as usize,
+        settlement.position[1] as usize,
+    )
+}
+
+/// Links every settlement to its nearest neighbours, keeps a minimum spanning tree of those
+/// links plus the shortcuts that save a long detour, and routes the kept links with A* from
+/// the shortest up so later roads can follow earlier ones.
+pub fn build_roads(
+    sea_level: f64,
+    heights: &DataGrid,
+    fresh_water: &DataGrid<bool>,
+    settlements: &[Settlement],
+    settings: &RoadSettings,
+) -> Vec<Road> {
+    println!("Building roads");
+    let mut field = CostField {
+        sea_level,
+        heights,
+        fresh_water,
+        roads: DataGrid::new(heights.width, heights.height),
+        settings,
+    };
+    let mut search = Search::new(heights.len());
+
+    let distance = |a: usize, b: usize| {
+        let dx = settlements[a].position[0] - settlements[b].position[0];
+        let dy = settlements[a].position[1] - settlements[b].position[1];
+        f64::sqrt(dx * dx + dy * dy)
+    };
+    let mut pairs = Vec::new();
+    for from in 0..settlements.len() {
+        let mut nearest: Vec<usize> = (0..settlements.len()).filter(|to| *to != from).collect();
+        nearest.sort_by(|a, b| distance(from, *a).total_cmp(&distance(from, *b)));
+        for to in nearest.into_iter().take(settings.links_per_settlement) {
+            pairs.push((usize::min(from, to), usize::max(from, to)));
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+
+    let mut candidates: Vec<(usize, usize, f64)> = pairs
+        .into_iter()
+        .filter_map(|(from, to)| {
+            field
+                .find_path(
+                    &mut search,
+                    settlement_cell(&settlements[from]),
+                    settlement_cell(&settlements[to]),
+                )
+                .map(|(_, cost)| (from, to, cost))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let mut parents: Vec<usize> = (0..settlements.len()).collect();
+    let mut links = Vec::new();
+    let mut skipped = Vec::new();
+    for (from, to, cost) in candidates {
+        let from_root = find_root(&mut parents, from);
+        let to_root = find_root(&mut parents, to);
+        if from_root == to_root {
+            skipped.push((from, to, cost));
+        } else {
+            parents[from_root] = to_root;
+            links.push((from, to, cost));
+        }
+    }
+    for (from, to, cost) in skipped {
+        if network_cost(&links, settlements.len(), from, to) > cost * settings.shortcut_ratio {
+            links.push((from, to, cost));
+        }
+    }
+    links.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let mut roads = Vec::new();
+    for (from, to, _) in links {
+        let path = field.find_path(
+            &mut search,
+            settlement_cell(&settlements[from]),
+            settlement_cell(&settlements[to]),
+        );
+        if let Some((points, cost)) = path {
+            draw_polyline(&mut field.roads, &points, true);
+            roads.push(Road {
+                from,
+                to,
+                points,
+                cost,
+            });
+        }
+    }
+    roads
+}
+
+/// Every pixel on the straight segments between consecutive points.
+pub fn rasterise(points: &[[f64; 2]]) -> Vec<(i32, i32)> {
+    let mut pixels = Vec::new();
+    for segment in points.windows(2) {
+        let start = (segment[0][0].round() as i32, segment[0][1].round() as i32);
+        let end = (segment[1][0].round() as i32, segment[1][1].round() as i32);
+        pixels.extend(Bresenham::new(start, end));
+    }
+    if let [point] = points {
+        pixels.push((point[0].round() as i32, point[1].round() as i32));
+    }
+    pixels
+}
+
+pub fn draw_polyline<T: Copy>(grid: &mut DataGrid<T>, points: &[[f64; 2]], value: T) {
+    for (x, y) in rasterise(points) {
+        if x >= 0 && y >= 0 {
+            grid.set(x as usize, y as usize, value);
+        }
+    }
+}
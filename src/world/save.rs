@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Border, DataGrid, Kingdom, Lake, PlaceNames, RegionCell, River, Road, Settlement, World,
+    WorldConfig,
+};
+
+const MAGIC: &[u8; 4] = b"WRLD";
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What a section holds, so tools can list and read layers they know nothing about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    /// UTF-8 RON text.
+    Ron = 0,
+    GridF64 = 1,
+    GridU32 = 2,
+    GridU8 = 3,
+}
+
+/// How a section's bytes are stored. Each section picks whichever is smallest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionCodec {
+    Stored = 0,
+    Deflate = 1,
+    /// Deflate over the samples split into byte planes, which suits float grids.
+    ShuffleDeflate = 2,
+}
+
+/// A named section of a world file, with its bytes uncompressed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Vector features and scalars, stored together as one RON section.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Features {
+    sea_level: f64,
+    biome_palette: Vec<[u8; 3]>,
+    lakes: Vec<Lake>,
+    rivers: Vec<River>,
+    coastlines: Vec<Vec<[f64; 2]>>,
+    region_cells: Vec<RegionCell>,
+    region_kingdoms: Vec<Option<usize>>,
+    kingdoms: Vec<Kingdom>,
+    borders: Vec<Border>,
+    settlements: Vec<Settlement>,
+    roads: Vec<Road>,
+    names: PlaceNames,
+}
+
+trait Sample: Copy + Default {
+    const KIND: SectionKind;
+    const SIZE: usize;
+    fn write(self, bytes: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Self;
+}
+
+impl Sample for f64 {
+    const KIND: SectionKind = SectionKind::GridF64;
+    const SIZE: usize = 8;
+    fn write(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+    fn read(bytes: &[u8]) -> Self {
+        f64::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Sample for u32 {
+    const KIND: SectionKind = SectionKind::GridU32;
+    const SIZE: usize = 4;
+    fn write(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+    fn read(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Sample for u8 {
+    const KIND: SectionKind = SectionKind::GridU8;
+    const SIZE: usize = 1;
+    fn write(self, bytes: &mut Vec<u8>) {
+        bytes.push(self);
+    }
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl SectionKind {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => SectionKind::Ron,
+            1 => SectionKind::GridF64,
+            2 => SectionKind::GridU32,
+            3 => SectionKind::GridU8,
+            _ => panic!("Unknown world section kind {}", value),
+        }
+    }
+    fn sample_size(&self) -> usize {
+        match self {
+            SectionKind::Ron | SectionKind::GridU8 => 1,
+            SectionKind::GridU32 => 4,
+            SectionKind::GridF64 => 8,
+        }
+    }
+}
+
+impl SectionCodec {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => SectionCodec::Stored,
+            1 => SectionCodec::Deflate,
+            2 => SectionCodec::ShuffleDeflate,
+            _ => panic!("Unknown world section codec {}", value),
+        }
+    }
+}
+
+impl Section {
+    fn grid<T: Sample>(name: &str, data_grid: &DataGrid<T>) -> Self {
+        let mut data = Vec::with_capacity(data_grid.len() * T::SIZE);
+        for value in data_grid.iter() {
+            value.write(&mut data);
+        }
+        Self {
+            name: name.to_string(),
+            kind: T::KIND,
+            width: data_grid.width,
+            height: data_grid.height,
+            data,
+        }
+    }
+    fn ron<T: Serialize>(name: &str, value: &T) -> Self {
+        let text = ron::to_string(value).expect("Could not serialise world section");
+        Self {
+            name: name.to_string(),
+            kind: SectionKind::Ron,
+            width: 0,
+            height: 0,
+            data: text.into_bytes(),
+        }
+    }
+    fn to_grid<T: Sample>(&self) -> DataGrid<T> {
+        assert_eq!(
+            self.kind,
+            T::KIND,
+            "World section '{}' has the wrong kind",
+            self.name
+        );
+        let size = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|cells| cells.checked_mul(T::SIZE));
+        assert!(
+            size == Some(self.data.len()),
+            "World section '{}' does not match its grid size",
+            self.name
+        );
+        let values = self.data.chunks_exact(T::SIZE).map(T::read).collect();
+        DataGrid::from_vec(self.width, self.height, values)
+    }
+    fn to_ron<T: for<'a> Deserialize<'a>>(&self) -> T {
+        let text = std::str::from_utf8(&self.data).expect("Could not read world section text");
+        ron::from_str(text).unwrap_or_else(|error| {
+            panic!("Could not parse world section '{}': {}", self.name, error)
+        })
+    }
+}
+
+fn shuffle(data: &[u8], sample_size: usize) -> Vec<u8> {
+    let count = data.len() / sample_size;
+    let mut shuffled = vec![0; data.len()];
+    for (i, sample) in data.chunks_exact(sample_size).enumerate() {
+        for (plane, byte) in sample.iter().enumerate() {
+            shuffled[plane * count + i] = *byte;
+        }
+    }
+    shuffled
+}
+
+fn unshuffle(data: &[u8], sample_size: usize) -> Vec<u8> {
+    let count = data.len() / sample_size;
+    let mut unshuffled = vec![0; data.len()];
+    for (plane, bytes) in data.chunks_exact(count.max(1)).enumerate() {
+        for (i, byte) in bytes.iter().enumerate() {
+            unshuffled[i * sample_size + plane] = *byte;
+        }
+    }
+    unshuffled
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .expect("Could not compress world section")
+}
+
+/// Decompresses at most one byte past the expected length, so a corrupt or hostile section can't
+/// make it allocate without bound. Deflate expands data at most about 1032 times.
+fn inflate(data: &[u8], length: usize) -> Vec<u8> {
+    let mut inflated = Vec::with_capacity(length.min(data.len().saturating_mul(1032)));
+    DeflateDecoder::new(data)
+        .take((length as u64).saturating_add(1))
+        .read_to_end(&mut inflated)
+        .expect("Could not decompress world section");
+    inflated
+}
+
+fn encode_section(bytes: &mut Vec<u8>, section: &Section) {
+    let sample_size = section.kind.sample_size();
+    let mut stored = (SectionCodec::Stored, section.data.clone());
+    let mut candidates = vec![(SectionCodec::Deflate, deflate(&section.data))];
+    if sample_size > 1 {
+        candidates.push((
+            SectionCodec::ShuffleDeflate,
+            deflate(&shuffle(&section.data, sample_size)),
+        ));
+    }
+    for candidate in candidates {
+        if candidate.1.len() < stored.1.len() {
+            stored = candidate;
+        }
+    }
+    bytes.extend_from_slice(&(section.name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(section.name.as_bytes());
+    bytes.push(section.kind as u8);
+    bytes.push(stored.0 as u8);
+    bytes.extend_from_slice(&(section.width as u32).to_le_bytes());
+    bytes.extend_from_slice(&(section.height as u32).to_le_bytes());
+    bytes.extend_from_slice(&(section.data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(stored.1.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&section.data).to_le_bytes());
+    bytes.extend_from_slice(&stored.1);
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, length: usize) -> &'a [u8] {
+        let end = self.position + length;
+        assert!(end <= self.bytes.len(), "World file is truncated");
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        taken
+    }
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().unwrap())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take(8).try_into().unwrap())
+    }
+}
+
+fn decode_section(reader: &mut ByteReader) -> Section {
+    let name_length = reader.u16() as usize;
+    let name = String::from_utf8(reader.take(name_length).to_vec())
+        .expect("Could not read world section name");
+    let kind = SectionKind::from_u8(reader.u8());
+    let codec = SectionCodec::from_u8(reader.u8());
+    let width = reader.u32() as usize;
+    let height = reader.u32() as usize;
+    let length = reader.u64() as usize;
+    let stored_length = reader.u64() as usize;
+    let checksum = reader.u32();
+    let stored = reader.take(stored_length);
+    let data = match codec {
+        SectionCodec::Stored => stored.to_vec(),
+        SectionCodec::Deflate | SectionCodec::ShuffleDeflate => inflate(stored, length),
+    };
+    assert_eq!(
+        data.len(),
+        length,
+        "World section '{}' has the wrong length",
+        name
+    );
+    let data = match codec {
+        SectionCodec::ShuffleDeflate => unshuffle(&data, kind.sample_size()),
+        _ => data,
+    };
+    assert_eq!(
+        crc32fast::hash(&data),
+        checksum,
+        "World section '{}' failed its checksum",
+        name
+    );
+    Section {
+        name,
+        kind,
+        width,
+        height,
+        data,
+    }
+}
+
+/// Writes sections into a world file container.
+pub fn write_sections(sections: &[Section]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    for section in sections {
+        encode_section(&mut bytes, section);
+    }
+    bytes
+}
+
+/// Reads every section of a world file container, checking each against its checksum.
+pub fn read_sections(bytes: &[u8]) -> Vec<Section> {
+    let mut reader = ByteReader { bytes, position: 0 };
+    assert!(reader.take(4) == MAGIC, "Not a world file");
+    let version = reader.u32();
+    assert!(
+        version <= FORMAT_VERSION,
+        "World file version {} is newer than the supported version {}",
+        version,
+        FORMAT_VERSION
+    );
+    let count = reader.u32();
+    (0..count).map(|_| decode_section(&mut reader)).collect()
+}
+
+impl World {
+    /// Serialises the generated world, so it can be loaded again without regenerating it. Each
+    /// layer is its own section, compressed and checksummed on its own.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let World {
+            config,
+            heights,
+            sediment,
+            water_flow,
+            lakes,
+            lake_labels,
+            rivers,
+            temperature,
+            moisture,
+            biomes,
+            biome_palette,
+            sea_level,
+            coastlines,
+            coast_distance,
+            regions,
+            region_kingdoms,
+            kingdoms,
+            borders,
+            settlements,
+            roads,
+            names,
+        } = self;
+        let features = Features {
+            sea_level: *sea_level,
+            biome_palette: biome_palette.clone(),
+            lakes: lakes.clone(),
+            rivers: rivers.clone(),
+            coastlines: coastlines.clone(),
+            region_cells: regions.cells.clone(),
+            region_kingdoms: region_kingdoms.clone(),
+            kingdoms: kingdoms.clone(),
+            borders: borders.clone(),
+            settlements: settlements.clone(),
+            roads: roads.clone(),
+            names: names.clone(),
+        };
+        write_sections(&[
+            Section::ron("config", config),
+            Section::ron("features", &features),
+            Section::grid("heights", heights),
+            Section::grid("sediment", sediment),
+            Section::grid("water_flow", water_flow),
+            Section::grid("lake_labels", lake_labels),
+            Section::grid("temperature", temperature),
+            Section::grid("moisture", moisture),
+            Section::grid("biomes", biomes),
+            Section::grid("coast_distance", coast_distance),
+            Section::grid("region_cell_ids", &regions.cell_ids),
+        ])
+    }
+
+    /// Rebuilds a world from `to_bytes`. Layers missing from older files are left empty and
+    /// sections this version does not know are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut sections: HashMap<String, Section> = read_sections(bytes)
+            .into_iter()
+            .map(|section| (section.name.clone(), section))
+            .collect();
+        let config: WorldConfig = sections
+            .remove("config")
+            .expect("World file has no config section")
+            .to_ron();
+        let mut world = World::new(config);
+        for (name, section) in sections {
+            match name.as_str() {
+                "features" => {
+                    let features: Features = section.to_ron();
+                    world.sea_level = features.sea_level;
+                    world.biome_palette = features.biome_palette;
+                    world.lakes = features.lakes;
+                    world.rivers = features.rivers;
+                    world.coastlines = features.coastlines;
+                    world.regions.cells = features.region_cells;
+                    world.region_kingdoms = features.region_kingdoms;
+                    world.kingdoms = features.kingdoms;
+                    world.borders = features.borders;
+                    world.settlements = features.settlements;
+                    world.roads = features.roads;
+                    world.names = features.names;
+                }
+                "heights" => world.heights = section.to_grid(),
+                "sediment" => world.sediment = section.to_grid(),
+                "water_flow" => world.water_flow = section.to_grid(),
+                "lake_labels" => world.lake_labels = section.to_grid(),
+                "temperature" => world.temperature = section.to_grid(),
+                "moisture" => world.moisture = section.to_grid(),
+                "biomes" => world.biomes = section.to_grid(),
+                "coast_distance" => world.coast_distance = section.to_grid(),
+                "region_cell_ids" => world.regions.cell_ids = section.to_grid(),
+                _ => println!("Skipping unknown world section '{}'", name),
+            }
+        }
+        world
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) {
+        println!("Saving world");
+        fs::write(path, self.to_bytes()).expect("Could not write world file");
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        println!("Loading world");
+        let bytes = fs::read(path).expect("Could not read world file");
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_world() -> World {
+        let mut world = World::new(WorldConfig {
+            width: 16,
+            height: 12,
+            ..WorldConfig::default()
+        });
+        world.heights = DataGrid::from_fn(16, 12, |x, y| (x * y) as f64 / 7.0 - 0.25);
+        world.lake_labels = DataGrid::from_fn(16, 12, |x, y| ((x + y) % 3) as u32);
+        world.biomes = DataGrid::from_fn(16, 12, |x, y| (x ^ y) as u8);
+        world.sea_level = 0.37;
+        world.rivers = vec![River {
+            points: vec![[1.0, 2.0], [2.0, 3.0]],
+            width: 0.5,
+            order: 1,
+            mouth: true,
+        }];
+        world
+    }
+
+    #[test]
+    fn world_round_trips() {
+        let world = test_world();
+        let read = World::from_bytes(&world.to_bytes());
+        assert_eq!(read.config, world.config);
+        assert_eq!(read.heights, world.heights);
+        assert_eq!(read.lake_labels, world.lake_labels);
+        assert_eq!(read.biomes, world.biomes);
+        assert_eq!(read.sea_level, world.sea_level);
+        assert_eq!(read.rivers, world.rivers);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed its checksum")]
+    fn corrupt_section_fails_its_checksum() {
+        let section = Section {
+            name: "noise".to_string(),
+            kind: SectionKind::GridU8,
+            width: 4,
+            height: 1,
+            data: vec![3, 141, 59, 26],
+        };
+        let mut bytes = write_sections(&[section]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        read_sections(&bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match its grid size")]
+    fn oversized_grid_header_is_rejected() {
+        let section = Section {
+            name: "heights".to_string(),
+            kind: SectionKind::GridF64,
+            width: usize::MAX / 2,
+            height: 3,
+            data: vec![0; 8],
+        };
+        section.to_grid::<f64>();
+    }
+}
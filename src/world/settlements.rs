@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+use super::{climate::distance_to, DataGrid, EdgeMode, RegionGraph, River};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettlementSize {
+    City,
+    Town,
+    Village,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettlementTier {
+    pub count: usize,
+    /// Minimum distance in cells to any settlement placed before this one.
+    pub spacing: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettlementSettings {
+    pub cities: SettlementTier,
+    pub towns: SettlementTier,
+    pub villages: SettlementTier,
+    pub flatness_weight: f64,
+    pub fresh_water_weight: f64,
+    pub coast_weight: f64,
+    pub fertility_weight: f64,
+    /// Slope at which flatness drops to a half.
+    pub flatness_slope: f64,
+    /// Distance in cells over which the pull of water halves.
+    pub water_distance: f64,
+    /// Temperature the best farmland grows at.
+    pub ideal_temperature: f64,
+    /// Weight of distance to the nearest settlement already placed, so new settlements favour
+    /// open country over the edges of existing ones.
+    pub isolation_weight: f64,
+    /// Distance in cells to the nearest settlement at which the pull of open country reaches a
+    /// half.
+    pub isolation_distance: f64,
+}
+
+impl Default for SettlementTier {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            spacing: 40.0,
+        }
+    }
+}
+
+impl Default for SettlementSettings {
+    fn default() -> Self {
+        Self {
+            cities: SettlementTier {
+                count: 4,
+                spacing: 160.0,
+            },
+            towns: SettlementTier {
+                count: 12,
+                spacing: 60.0,
+            },
+            villages: SettlementTier {
+                count: 40,
+                spacing: 25.0,
+            },
+            flatness_weight: 1.0,
+            fresh_water_weight: 1.0,
+            coast_weight: 0.5,
+            fertility_weight: 1.0,
+            flatness_slope: 0.005,
+            water_distance: 16.0,
+            ideal_temperature: 18.0,
+            isolation_weight: 0.5,
+            isolation_distance: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub position: [f64; 2],
+    pub size: SettlementSize,
+    /// Region cell the settlement lies in, when a region graph was built.
+    pub cell: Option<usize>,
+    pub score: f64,
+}
+
+pub fn fresh_water_mask(lake_labels: &DataGrid<u32>, rivers: &[River]) -> DataGrid<bool> {
+    let mut fresh_water = lake_labels.map(|lake| lake != 0);
+    for river in rivers {
+        for point in river.land_points() {
+            fresh_water.set(point[0] as usize, point[1] as usize, true);
+        }
+    }
+    fresh_water
+}
+
+/// Scores every dry land cell for how well it suits a settlement, from zero upwards.
+pub fn suitability(
+    sea_level: f64,
+    heights: &DataGrid,
+    fresh_water: &DataGrid<bool>,
+    coast_distance: &DataGrid,
+    temperature: &DataGrid,
+    moisture: &DataGrid,
+    settings: &SettlementSettings,
+) -> DataGrid {
+    let fresh_water_distance = distance_to(fresh_water);
+    let slope = heights.slope(EdgeMode::Clamp);
+    heights.map_coords(|x, y, height| {
+        let index = heights.index_of(x, y);
+        if height < sea_level || fresh_water.values[index] {
+            return 0.0;
+        }
+        let flatness = 1.0 / (1.0 + slope.values[index] / settings.flatness_slope);
+        let fresh_water = f64::powf(
+            0.5,
+            fresh_water_distance.values[index] / settings.water_distance,
+        );
+        let coast = f64::powf(0.5, coast_distance.values[index] / settings.water_distance);
+        let warmth = (temperature.values[index] - settings.ideal_temperature) / 15.0;
+        let fertility = moisture.values[index] * f64::exp(-warmth * warmth);
+        settings.flatness_weight * flatness
+            + settings.fresh_water_weight * fresh_water
+            + settings.coast_weight * coast
+            + settings.fertility_weight * fertility
+    })
+}
+
+/// Places cities first, then towns, then villages. Each one goes on the cell that scores best
+/// on suitability plus distance to the nearest settlement already placed, among the cells that
+/// keep its tier's spacing from every settlement.
+pub fn place_settlements(
+    suitability: &DataGrid,
+    regions: &RegionGraph,
+    settings: &SettlementSettings,
+) -> Vec<Settlement> {
+    println!("Placing settlements");
+    let mut candidates: Vec<usize> = (0..suitability.len())
+        .filter(|index| suitability.values[*index] > 0.0)
+        .collect();
+    candidates.sort_by(|a, b| {
+        suitability.values[*b]
+            .total_cmp(&suitability.values[*a])
+            .then_with(|| a.cmp(b))
+    });
+    let mut nearest = vec![f64::INFINITY; candidates.len()];
+    let score = |index: usize, nearest: f64| {
+        let isolation = 1.0 - f64::powf(0.5, nearest / settings.isolation_distance);
+        suitability.values[index] + settings.isolation_weight * isolation
+    };
+
+    let tiers = [
+        (SettlementSize::City, &settings.cities),
+        (SettlementSize::Town, &settings.towns),
+        (SettlementSize::Village, &settings.villages),
+    ];
+    let mut settlements: Vec<Settlement> = Vec::new();
+    for (size, tier) in tiers {
+        for _ in 0..tier.count {
+            let best = candidates
+                .iter()
+                .zip(&nearest)
+                .filter(|(_, nearest)| **nearest >= tier.spacing)
+                .map(|(index, nearest)| (*index, score(*index, *nearest)))
+                .min_by(|a, b| b.1.total_cmp(&a.1));
+            let (index, score) = match best {
+                Some(best) => best,
+                None => break,
+            };
+            let (x, y) = suitability.coords_of(index);
+            let position = [x as f64, y as f64];
+            for (candidate, nearest) in candidates.iter().zip(&mut nearest) {
+                let (candidate_x, candidate_y) = suitability.coords_of(*candidate);
+                let dx = candidate_x as f64 - position[0];
+                let dy = candidate_y as f64 - position[1];
+                *nearest = f64::min(*nearest, f64::sqrt(dx * dx + dy * dy));
+            }
+            settlements.push(Settlement {
+                position,
+                size,
+                cell: regions.cell_at(x, y).map(|cell| cell.id),
+                score,
+            });
+        }
+    }
+    settlements
+}
@@ -0,0 +1,231 @@
+use std::f64::consts::{PI, TAU};
+
+use fast_poisson::Poisson2D;
+use image::io::Reader;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::{BlendMode, DataGrid, LayerGraph, WorldConfig};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StampProfile {
+    Crater,
+    Volcano,
+    Mesa,
+    Ridge,
+    Dune,
+    ImpactBasin,
+    /// Greyscale image where mid grey leaves the terrain unchanged.
+    Image {
+        path: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StampPlacement {
+    Uniform,
+    Poisson {
+        spacing: f64,
+    },
+    /// Places stamps with a probability given by a layer recipe, clamped to `[0, 1]`.
+    Mask {
+        recipe: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StampSettings {
+    pub profile: StampProfile,
+    pub placement: StampPlacement,
+    pub count: usize,
+    /// Range of stamp radii in cells.
+    pub scale: [f64; 2],
+    /// Range of rotations in radians.
+    pub rotation: [f64; 2],
+    /// Range of heights added at the strongest point of the profile.
+    pub strength: [f64; 2],
+    /// Range of exponents for the fade towards the stamp edge. Higher values fade sooner.
+    pub falloff: [f64; 2],
+    /// How the signed stamp heights combine with the terrain.
+    pub blend: BlendMode,
+}
+
+impl Default for StampSettings {
+    fn default() -> Self {
+        Self {
+            profile: StampProfile::Crater,
+            placement: StampPlacement::Uniform,
+            count: 5,
+            scale: [16.0, 80.0],
+            rotation: [0.0, TAU],
+            strength: [0.02, 0.1],
+            falloff: [0.5, 2.0],
+            blend: BlendMode::Add,
+        }
+    }
+}
+
+fn smoothstep(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Height of a profile at local coordinates, where the stamp covers the unit disc and the
+/// strongest point has a magnitude of about one. Elongated profiles run along `u`.
+fn profile_height(profile: &StampProfile, image: Option<&DataGrid>, u: f64, v: f64) -> f64 {
+    let r = f64::sqrt(u * u + v * v);
+    match profile {
+        StampProfile::Crater => {
+            let rim = 0.5 * f64::exp(-((r - 0.7) / 0.12).powi(2));
+            let bowl = if r < 0.7 {
+                (r / 0.7).powi(2) - 1.0
+            } else {
+                0.0
+            };
+            bowl + rim
+        }
+        StampProfile::Volcano => (1.0 - r).max(0.0) - 0.6 * f64::exp(-(r / 0.12).powi(2)),
+        StampProfile::Mesa => 1.0 - smoothstep(0.45, 0.65, r),
+        StampProfile::Ridge => (1.0 - v.abs() / 0.3).max(0.0).powf(1.5) * (1.0 - u * u).max(0.0),
+        StampProfile::Dune => {
+            let across = if v < 0.0 {
+                1.0 + v / 0.6
+            } else {
+                1.0 - v / 0.15
+            };
+            across.max(0.0) * (1.0 - u * u).max(0.0)
+        }
+        StampProfile::ImpactBasin => {
+            -0.8 * (1.0 - r * r).max(0.0) + 0.2 * f64::sin(r * 3.0 * PI) * (1.0 - r).max(0.0)
+        }
+        StampProfile::Image { .. } => match image {
+            Some(image) => {
+                let x = (u * 0.5 + 0.5) * (image.width - 1) as f64;
+                let y = (v * 0.5 + 0.5) * (image.height - 1) as f64;
+                (image.sample(x, y) - 0.5) * 2.0
+            }
+            None => 0.0,
+        },
+    }
+}
+
+/// Renders one stamp into a square grid of signed height changes, centred on the middle cell.
+fn render_stamp(
+    profile: &StampProfile,
+    image: Option<&DataGrid>,
+    radius: f64,
+    rotation: f64,
+    strength: f64,
+    falloff: f64,
+) -> DataGrid {
+    let reach = radius.ceil() as usize;
+    let (sin, cos) = rotation.sin_cos();
+    DataGrid::from_fn(reach * 2 + 1, reach * 2 + 1, |x, y| {
+        let dx = (x as f64 - reach as f64) / radius;
+        let dy = (y as f64 - reach as f64) / radius;
+        let u = dx * cos + dy * sin;
+        let v = -dx * sin + dy * cos;
+        let r_2 = u * u + v * v;
+        if r_2 >= 1.0 {
+            return 0.0;
+        }
+        profile_height(profile, image, u, v) * (1.0 - r_2).powf(falloff) * strength
+    })
+}
+
+fn in_range<R: Rng>(random: &mut R, range: [f64; 2]) -> f64 {
+    range[0] + (range[1] - range[0]) * random.gen::<f64>()
+}
+
+fn stamp_positions<R: Rng>(
+    config: &WorldConfig,
+    settings: &StampSettings,
+    stream: &str,
+    random: &mut R,
+) -> Vec<[f64; 2]> {
+    let (width, height) = (config.width as f64, config.height as f64);
+    match &settings.placement {
+        StampPlacement::Uniform => (0..settings.count)
+            .map(|_| [random.gen::<f64>() * width, random.gen::<f64>() * height])
+            .collect(),
+        StampPlacement::Poisson { spacing } => {
+            let mut points: Vec<[f64; 2]> = Poisson2D::new()
+                .with_dimensions([width, height], *spacing)
+                .with_seed(config.stream_seed(stream))
+                .iter()
+                .collect();
+            points.shuffle(random);
+            points.truncate(settings.count);
+            points
+        }
+        StampPlacement::Mask { recipe } => {
+            let density = LayerGraph::from_file(recipe).evaluate(config);
+            let mut points = Vec::new();
+            for _ in 0..settings.count * 100 {
+                if points.len() >= settings.count {
+                    break;
+                }
+                let point = [random.gen::<f64>() * width, random.gen::<f64>() * height];
+                let chance = density
+                    .get(point[0] as usize, point[1] as usize)
+                    .unwrap_or(0.0)
+                    .clamp(0.0, 1.0);
+                if random.gen::<f64>() < chance {
+                    points.push(point);
+                }
+            }
+            points
+        }
+    }
+}
+
+/// Adds every stamp layer to the heights in order. Each layer draws from its own random stream,
+/// so editing one layer leaves the stamps of the others where they were.
+pub fn apply_stamps(
+    config: &WorldConfig,
+    heights: &DataGrid,
+    stamps: &[StampSettings],
+) -> DataGrid {
+    println!("Applying stamps");
+    let mut heights = heights.clone();
+    for (i, settings) in stamps.iter().enumerate() {
+        let image = match &settings.profile {
+            StampProfile::Image { path } => match Reader::open(path)
+                .ok()
+                .and_then(|reader| reader.decode().ok())
+            {
+                Some(image) => Some(DataGrid::from_image(&image.into_luma8())),
+                None => {
+                    println!("Could not load stamp image {}, skipping", path);
+                    continue;
+                }
+            },
+            _ => None,
+        };
+        let stream = format!("stamps/{}", i);
+        let mut random = config.rng(&stream);
+        for position in stamp_positions(config, settings, &stream, &mut random) {
+            let radius = f64::max(in_range(&mut random, settings.scale), 1.0);
+            let stamp = render_stamp(
+                &settings.profile,
+                image.as_ref(),
+                radius,
+                in_range(&mut random, settings.rotation),
+                in_range(&mut random, settings.strength),
+                f64::max(in_range(&mut random, settings.falloff), 0.0),
+            );
+            let reach = (stamp.width / 2) as f64;
+            heights.blend(
+                &stamp,
+                [position[0] - reach, position[1] - reach],
+                settings.blend,
+                1.0,
+                None,
+            );
+        }
+    }
+    heights
+}